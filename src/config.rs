@@ -1,17 +1,241 @@
 use mime_guess::{mime, Mime};
+use regex::Regex;
 use serde::Deserialize;
-use std::convert::From;
+use std::convert::TryFrom;
 use std::ffi::{OsStr, OsString};
 use std::fs::File;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::layout::Layout;
+
+/// How files which are not projected are presented in the mount
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PassThroughMode {
+    Show,
+    Hide,
+}
+
+/// How the files of a directory which would appear under the same name in the mount are told apart
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionPolicy {
+    /// The first one (by source file name) keeps the name; the others are hidden
+    First,
+    /// The file provided as-is keeps the name (or the first one if there is none); the others are hidden
+    Passthrough,
+    /// The file provided as-is (or the first one) keeps the name; the others get a `~N` suffix before their extension
+    Suffix,
+    /// The projected files keep the extension of their source file before the new one (e.g. `a.flac.ogg`)
+    Extension,
+}
+
+/// An extra file generated from each matching source file, appearing next to it
+pub struct Sidecar {
+    /// Appended to the name of the source file to form the name of the sidecar file
+    pub suffix: String,
+    /// The maximum total size (in bytes) of the sidecar files of this specification in the cache
+    pub quota: Option<u64>,
+    mime_types: Option<Vec<Mime>>,
+    command: Vec<String>,
+    reverse_command: Option<Vec<String>>,
+    fingerprint: u64,
+}
+
+impl Sidecar {
+    pub fn applies_to(&self, path: &Path) -> bool {
+        match &self.mime_types {
+            Some(mime_types) => match mime_guess::from_path(path).first() {
+                Some(mime) => in_mime_vec(&mime, mime_types),
+                None => false,
+            },
+            None => true,
+        }
+    }
+
+    /// Identifies how the sidecar files are generated
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Generates the sidecar file `output` from `input`
+    pub fn generate(&self, input: &OsStr, output: &OsStr) -> Result<(), String> {
+        debug!("sidecar generation: {:?} -> {:?}", input, output);
+        run_conversion(&self.command, input, output, "sidecar")
+    }
+
+    /// Whether the sidecar files can be converted back into their source file
+    pub fn reversible(&self) -> bool {
+        self.reverse_command.is_some()
+    }
+
+    /// Converts the (modified) sidecar file `input` back into the source file `output`
+    pub fn reverse(&self, input: &OsStr, output: &OsStr) -> Result<(), String> {
+        debug!("reverse sidecar conversion: {:?} -> {:?}", input, output);
+        match &self.reverse_command {
+            Some(command) => run_conversion(command, input, output, "reverse sidecar"),
+            None => Err("no reverse command".to_string()),
+        }
+    }
+}
+
+/// Runs `command` (already split into segments) to convert `input` into `output`.
+/// If the command doesn't take `{output}`, its standard output is written to `output`,
+/// which then exists (and grows) from the start of the conversion.
+fn run_conversion(
+    command: &[String],
+    input: &OsStr,
+    output: &OsStr,
+    what: &str,
+) -> Result<(), String> {
+    let writes_output = command.iter().any(|s| s.contains("{output}"));
+    let segments: Vec<OsString> = command
+        .iter()
+        .map(|s| replace_paths(s, input, output))
+        .collect();
+    let mut cmd = match segments.split_first() {
+        Some((program, args)) => {
+            let mut cmd = Command::new(program);
+            cmd.args(args);
+            cmd
+        }
+        None => return Err(format!("empty {} command", what)),
+    };
+    if !writes_output {
+        match File::create(output) {
+            Ok(f) => {
+                cmd.stdout(Stdio::from(f));
+            }
+            Err(e) => return Err(format!("can't create {} file {:?}: {}", what, output, e)),
+        }
+    }
+    match cmd.spawn() {
+        Ok(mut child) => match child.wait() {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(format!("{} command {}", what, status)),
+            Err(e) => Err(format!("failed to wait for {} command: {}", what, e)),
+        },
+        Err(e) => Err(format!("failed to execute {} command: {}", what, e)),
+    }
+}
+
+/// Replaces every `{input}` and `{output}` in the command segment `segment` by the paths, which may not be UTF-8
+fn replace_paths(segment: &str, input: &OsStr, output: &OsStr) -> OsString {
+    let mut replaced = OsString::new();
+    for (i, part) in segment.split("{input}").enumerate() {
+        if i > 0 {
+            replaced.push(input);
+        }
+        for (j, part) in part.split("{output}").enumerate() {
+            if j > 0 {
+                replaced.push(output);
+            }
+            replaced.push(part);
+        }
+    }
+    replaced
+}
 
 pub trait ProjectionSpecification: Send + Sync {
     fn should_project(&self, mime: &Mime) -> bool;
 
     fn convert_filename(&self, filename: &OsStr) -> OsString;
 
-    fn project(&self, input: &OsStr, output: &OsStr);
+    /// Converts `input` into `output`
+    fn project(&self, input: &OsStr, output: &OsStr) -> Result<(), String>;
+
+    /// Whether projected files can be converted back into their source file (see `reverse()`)
+    fn reversible(&self) -> bool {
+        false
+    }
+
+    /// Converts the (modified) projected file `input` back into the source file `output`
+    fn reverse(&self, _input: &OsStr, _output: &OsStr) -> Result<(), String> {
+        Err("no reverse command".to_string())
+    }
+
+    fn passthrough(&self) -> PassThroughMode {
+        PassThroughMode::Show
+    }
+
+    /// Whether `partial`, or one of the directories it's in, is excluded.
+    /// parameter `partial` is the path relative to the source directory (without the leading `/`)
+    fn is_excluded(&self, _partial: &Path) -> bool {
+        false
+    }
+
+    fn prune_empty_dirs(&self) -> bool {
+        false
+    }
+
+    /// How the files of a directory which would appear under the same name are told apart
+    fn collision(&self) -> CollisionPolicy {
+        CollisionPolicy::First
+    }
+
+    /// Whether the original of a projected file is still provided (as-is) besides the projected one
+    fn show_original(&self) -> bool {
+        false
+    }
+
+    /// Whether `rewrite_path()` may change any path, i.e. whether the mount has its own hierarchy
+    fn rewrites_paths(&self) -> bool {
+        false
+    }
+
+    /// Computes where a file appears in the mount according to its metadata, or `None` if the path is not affected.
+    /// parameter `source` is the real path of the source file; `partial` is the path (without the leading `/`) it would appear at otherwise
+    fn layout_path(&self, _source: &OsStr, _partial: &Path) -> Option<PathBuf> {
+        None
+    }
+
+    /// Estimates the size of a file not generated yet, from the size of its source file
+    fn placeholder_size(&self, source_size: u64) -> u64 {
+        source_size
+    }
+
+    /// The sidecar files generated for source files
+    fn sidecars(&self) -> &[Sidecar] {
+        &[]
+    }
+
+    /// The maximum total size (in bytes) of the projected files in the cache
+    fn quota(&self) -> Option<u64> {
+        None
+    }
+
+    /// Identifies how files are projected, so projected files can be told apart when the projection changes
+    fn fingerprint(&self) -> u64;
+
+    /// Computes where an entry appears in the mount.
+    /// parameter `partial` is the path (without the leading `/`) the entry would appear at without rewriting
+    fn rewrite_path(&self, partial: &Path) -> PathBuf {
+        partial.to_owned()
+    }
+}
+
+fn fingerprint_of(parts: &[&str]) -> u64 {
+    seahash::hash(parts.join("\0").as_bytes())
+}
+
+/// Runs `command` (split the same way as `projection_command`) and returns its standard output,
+/// which identifies the version of a converter
+fn command_version(command: &str) -> Result<String, String> {
+    let segments: Vec<&str> = command.split(" ").collect();
+    match Command::new(segments[0]).args(&segments[1..]).output() {
+        Ok(output) if output.status.success() => {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        Ok(output) => Err(format!(
+            "version command {:?} exited with {}",
+            command, output.status
+        )),
+        Err(e) => Err(format!(
+            "failed to execute version command {:?}: {}",
+            command, e
+        )),
+    }
 }
 
 fn user_string_to_mime(string_mime_types: &Vec<String>) -> Vec<Mime> {
@@ -28,7 +252,7 @@ fn user_string_to_mime(string_mime_types: &Vec<String>) -> Vec<Mime> {
     mime_types
 }
 
-fn in_mime_vec(mime: &Mime, mime_types: &Vec<Mime>) -> bool {
+pub fn in_mime_vec(mime: &Mime, mime_types: &Vec<Mime>) -> bool {
     for m in mime_types {
         if mime.type_() == m.type_() {
             if m.subtype() == "" {
@@ -41,26 +265,201 @@ fn in_mime_vec(mime: &Mime, mime_types: &Vec<Mime>) -> bool {
     false
 }
 
+/// Parses a size in bytes, optionally followed by a binary unit (e.g. `512`, `300M`, `1.5GiB`)
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let number_end = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(number_end);
+    let number: f64 = number
+        .parse()
+        .map_err(|e| format!("invalid size {:?}: {}", text, e))?;
+    let unit = unit.trim().to_uppercase();
+    let unit = unit.trim_end_matches("IB").trim_end_matches('B');
+    let multiplier: u64 = match unit {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("invalid size {:?}: unknown unit", text)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+fn user_string_to_regex(patterns: &Vec<String>) -> Result<Vec<Regex>, String> {
+    let mut regexes = Vec::new();
+    for pattern in patterns {
+        match Regex::new(pattern) {
+            Ok(regex) => regexes.push(regex),
+            Err(e) => return Err(format!("invalid pattern {:?}: {}", pattern, e)),
+        }
+    }
+    Ok(regexes)
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct PlainRewriteRule {
+    pattern: String,
+    replacement: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct PlainLayout {
+    template: String,
+    extractor: Option<String>,
+    mime_types: Option<Vec<String>>,
+    unknown: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct PlainSidecar {
+    suffix: String,
+    command: String,
+    mime_types: Option<Vec<String>>,
+    quota: Option<PlainSize>,
+    version_command: Option<String>,
+    reverse_command: Option<String>,
+}
+
+/// Either a number of bytes, or a text (e.g. `"20%"`, or `"300M"`)
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum PlainSize {
+    Bytes(u64),
+    Text(String),
+}
+
+impl PlainSize {
+    fn bytes(&self) -> Result<u64, String> {
+        match self {
+            PlainSize::Bytes(bytes) => Ok(*bytes),
+            PlainSize::Text(text) => parse_size(text),
+        }
+    }
+}
+
+enum PlaceholderSize {
+    Bytes(u64),
+    Ratio(f64),
+}
+
+impl TryFrom<&PlainSize> for PlaceholderSize {
+    type Error = String;
+
+    fn try_from(plain: &PlainSize) -> Result<Self, Self::Error> {
+        match plain {
+            PlainSize::Bytes(bytes) => Ok(PlaceholderSize::Bytes(*bytes)),
+            PlainSize::Text(text) => {
+                let parsed = if text.ends_with("%") {
+                    text[..text.len() - 1]
+                        .trim()
+                        .parse::<f64>()
+                        .map(|percentage| PlaceholderSize::Ratio(percentage / 100.0))
+                        .map_err(|e| format!("invalid size {:?}: {}", text, e))
+                } else {
+                    parse_size(text).map(PlaceholderSize::Bytes)
+                };
+                parsed
+            }
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Deserialize)]
 struct PlainConfig {
     mime_types: Vec<String>,
     ignored_mime_types: Option<Vec<String>>,
     name_mapping: String,
     projection_command: String,
+    passthrough: Option<PassThroughMode>,
+    collision: Option<CollisionPolicy>,
+    exclude: Option<Vec<String>>,
+    prune_empty_dirs: Option<bool>,
+    show_original: Option<bool>,
+    rewrite: Option<Vec<PlainRewriteRule>>,
+    layout: Option<PlainLayout>,
+    sidecars: Option<Vec<PlainSidecar>>,
+    placeholder_size: Option<PlainSize>,
+    quota: Option<PlainSize>,
+    version_command: Option<String>,
+    reverse_command: Option<String>,
 }
 
 struct ProjectionConfig {
     mime_types: Vec<Mime>,
     ignored_mime_types: Vec<Mime>,
     name_mapping: Box<dyn Fn(&OsStr) -> OsString + Sync + Send>,
-    projection_command: Box<dyn Fn(&OsStr, &OsStr) + Sync + Send>,
+    projection_command: Box<dyn Fn(&OsStr, &OsStr) -> Result<(), String> + Sync + Send>,
+    reverse_command: Option<Vec<String>>,
+    passthrough: PassThroughMode,
+    collision: CollisionPolicy,
+    exclude: Vec<Regex>,
+    prune_empty_dirs: bool,
+    show_original: bool,
+    rewrite: Vec<(Regex, String)>,
+    layout: Option<Layout>,
+    sidecars: Vec<Sidecar>,
+    placeholder_size: Option<PlaceholderSize>,
+    quota: Option<u64>,
+    fingerprint: u64,
 }
 
-impl From<PlainConfig> for ProjectionConfig {
-    fn from(plain: PlainConfig) -> Self {
+impl TryFrom<PlainConfig> for ProjectionConfig {
+    type Error = String;
+
+    fn try_from(plain: PlainConfig) -> Result<Self, Self::Error> {
         let mime_types = user_string_to_mime(plain.mime_types.as_ref());
         let ignored_mime_types =
             user_string_to_mime(plain.ignored_mime_types.as_ref().unwrap_or(&Vec::new()));
+        let exclude = user_string_to_regex(plain.exclude.as_ref().unwrap_or(&Vec::new()))?;
+        let placeholder_size = match &plain.placeholder_size {
+            Some(size) => Some(PlaceholderSize::try_from(size)?),
+            None => None,
+        };
+        let quota = match &plain.quota {
+            Some(size) => Some(size.bytes()?),
+            None => None,
+        };
+        let mut sidecars = Vec::new();
+        for sidecar in plain.sidecars.as_ref().unwrap_or(&Vec::new()) {
+            let version = match &sidecar.version_command {
+                Some(command) => command_version(command)?,
+                None => String::new(),
+            };
+            sidecars.push(Sidecar {
+                suffix: if sidecar.suffix.starts_with(".") {
+                    sidecar.suffix.clone()
+                } else {
+                    format!(".{}", sidecar.suffix)
+                },
+                quota: match &sidecar.quota {
+                    Some(size) => Some(size.bytes()?),
+                    None => None,
+                },
+                mime_types: sidecar.mime_types.as_ref().map(user_string_to_mime),
+                command: sidecar.command.split(" ").map(|s| s.into()).collect(),
+                reverse_command: sidecar
+                    .reverse_command
+                    .as_ref()
+                    .map(|command| command.split(" ").map(|s| s.into()).collect()),
+                fingerprint: fingerprint_of(&[&sidecar.command, &version]),
+            });
+        }
+        let mut rewrite = Vec::new();
+        for rule in plain.rewrite.as_ref().unwrap_or(&Vec::new()) {
+            match Regex::new(&rule.pattern) {
+                Ok(regex) => rewrite.push((regex, rule.replacement.clone())),
+                Err(e) => return Err(format!("invalid pattern {:?}: {}", rule.pattern, e)),
+            }
+        }
+        let version = match &plain.version_command {
+            Some(command) => command_version(command)?,
+            None => String::new(),
+        };
+        let fingerprint =
+            fingerprint_of(&[&plain.projection_command, &plain.name_mapping, &version]);
         let _name_mapping = {
             let mapping = &(&plain).name_mapping;
             if mapping.starts_with(".") {
@@ -81,23 +480,36 @@ impl From<PlainConfig> for ProjectionConfig {
             .map(|s| s.into())
             .collect();
         let projection_command = move |input: &OsStr, output: &OsStr| {
-            let segments: Vec<String> = parts
-                .iter()
-                .map(|s| s.replace("{input}", input.to_str().unwrap()))
-                .map(|s| s.replace("{output}", output.to_str().unwrap()))
-                .collect();
-            let mut cmd = Command::new(&segments[0])
-                .args(&segments[1..])
-                .spawn()
-                .expect("failed to execute process");
-            cmd.wait().unwrap();
+            run_conversion(&parts, input, output, "projection")
         };
-        ProjectionConfig {
+        Ok(ProjectionConfig {
             mime_types: mime_types,
             ignored_mime_types: ignored_mime_types,
             name_mapping: Box::new(name_mapping),
             projection_command: Box::new(projection_command),
-        }
+            reverse_command: plain
+                .reverse_command
+                .as_ref()
+                .map(|command| command.split(" ").map(|s| s.into()).collect()),
+            passthrough: plain.passthrough.unwrap_or(PassThroughMode::Show),
+            collision: plain.collision.unwrap_or(CollisionPolicy::First),
+            exclude: exclude,
+            prune_empty_dirs: plain.prune_empty_dirs.unwrap_or(false),
+            show_original: plain.show_original.unwrap_or(false),
+            rewrite: rewrite,
+            placeholder_size: placeholder_size,
+            quota: quota,
+            fingerprint: fingerprint,
+            sidecars: sidecars,
+            layout: plain.layout.map(|layout| {
+                Layout::new(
+                    layout.template,
+                    layout.extractor,
+                    layout.mime_types.as_ref().map(user_string_to_mime),
+                    layout.unknown,
+                )
+            }),
+        })
     }
 }
 
@@ -110,9 +522,101 @@ impl ProjectionSpecification for ProjectionConfig {
         return (self.name_mapping)(filename.as_ref());
     }
 
-    fn project(&self, input: &OsStr, output: &OsStr) {
+    fn project(&self, input: &OsStr, output: &OsStr) -> Result<(), String> {
         (self.projection_command)(input, output)
     }
+
+    fn reversible(&self) -> bool {
+        self.reverse_command.is_some()
+    }
+
+    fn reverse(&self, input: &OsStr, output: &OsStr) -> Result<(), String> {
+        debug!("reverse projection: {:?} -> {:?}", input, output);
+        match &self.reverse_command {
+            Some(command) => run_conversion(command, input, output, "reverse projection"),
+            None => Err("no reverse command".to_string()),
+        }
+    }
+
+    fn passthrough(&self) -> PassThroughMode {
+        self.passthrough
+    }
+
+    fn collision(&self) -> CollisionPolicy {
+        self.collision
+    }
+
+    fn is_excluded(&self, partial: &Path) -> bool {
+        // Whatever is under an excluded directory is excluded as well, even when reached directly
+        partial
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .any(|ancestor| {
+                let ancestor = ancestor.to_string_lossy();
+                self.exclude.iter().any(|regex| regex.is_match(&ancestor))
+            })
+    }
+
+    fn prune_empty_dirs(&self) -> bool {
+        self.prune_empty_dirs
+    }
+
+    fn show_original(&self) -> bool {
+        self.show_original
+    }
+
+    fn rewrites_paths(&self) -> bool {
+        !self.rewrite.is_empty() || self.layout.is_some()
+    }
+
+    fn placeholder_size(&self, source_size: u64) -> u64 {
+        match self.placeholder_size {
+            Some(PlaceholderSize::Bytes(bytes)) => bytes,
+            Some(PlaceholderSize::Ratio(ratio)) => (source_size as f64 * ratio) as u64,
+            None => source_size,
+        }
+    }
+
+    fn sidecars(&self) -> &[Sidecar] {
+        &self.sidecars
+    }
+
+    fn quota(&self) -> Option<u64> {
+        self.quota
+    }
+
+    fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    fn layout_path(&self, source: &OsStr, partial: &Path) -> Option<PathBuf> {
+        match &self.layout {
+            Some(layout) => layout.path_of(source, partial),
+            None => None,
+        }
+    }
+
+    fn rewrite_path(&self, partial: &Path) -> PathBuf {
+        let mut path = match partial.to_str() {
+            Some(path) => path.to_string(),
+            None => {
+                warn!("{:?} is not valid UTF-8; not rewriting it", partial);
+                return partial.to_owned();
+            }
+        };
+        for (regex, replacement) in &self.rewrite {
+            path = regex.replace_all(&path, replacement.as_str()).into_owned();
+        }
+        let rewritten = PathBuf::from(path.trim_start_matches('/'));
+        if rewritten.as_os_str().is_empty() {
+            warn!(
+                "{:?} is rewritten to an empty path; not rewriting it",
+                partial
+            );
+            return partial.to_owned();
+        }
+        rewritten
+    }
 }
 
 pub fn load(filename: &OsStr) -> Option<Box<dyn ProjectionSpecification>> {
@@ -136,7 +640,16 @@ pub fn load(filename: &OsStr) -> Option<Box<dyn ProjectionSpecification>> {
             return None;
         }
     };
-    Some(Box::new(ProjectionConfig::from(plain_config)))
+    match ProjectionConfig::try_from(plain_config) {
+        Ok(config) => Some(Box::new(config)),
+        Err(e) => {
+            error!(
+                "Invalid projection configuration file {:?}: {}",
+                filename, e
+            );
+            None
+        }
+    }
 }
 
 struct DefaultConfig;
@@ -152,18 +665,23 @@ impl DefaultConfig {
         path_buf.set_extension("ogg");
         path_buf.into_os_string()
     }
-    fn _do_proj(input: &OsStr, output: &OsStr) {
+    fn _do_proj(input: &OsStr, output: &OsStr) -> Result<(), String> {
         debug!("do_proj() call: {:?} -> {:?}", input, output);
-        let mut cmd = Command::new("ffmpeg") // Streaming
-            .args(&[
-                "-i",
-                input.to_str().unwrap(),
-                "-vn",
-                output.to_str().unwrap(),
-            ])
+        let mut cmd = match Command::new("ffmpeg") // Streaming
+            .arg("-i")
+            .arg(input)
+            .arg("-vn")
+            .arg(output)
             .spawn()
-            .expect("failed to execute process");
-        cmd.wait().unwrap();
+        {
+            Ok(cmd) => cmd,
+            Err(e) => return Err(format!("failed to execute projection command: {}", e)),
+        };
+        match cmd.wait() {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(format!("projection command {}", status)),
+            Err(e) => Err(format!("failed to wait for projection command: {}", e)),
+        }
     }
 }
 
@@ -176,11 +694,54 @@ impl ProjectionSpecification for DefaultConfig {
         DefaultConfig::_filename_conv(filename)
     }
 
-    fn project(&self, input: &OsStr, output: &OsStr) {
+    fn project(&self, input: &OsStr, output: &OsStr) -> Result<(), String> {
         DefaultConfig::_do_proj(input, output)
     }
+
+    fn fingerprint(&self) -> u64 {
+        fingerprint_of(&["ffmpeg -i {input} -vn {output}", "ogg"])
+    }
 }
 
 pub fn default() -> Box<dyn ProjectionSpecification> {
     Box::new(DefaultConfig {})
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::ffi::OsStrExt;
+
+    fn parse(yaml: &str) -> ProjectionConfig {
+        let plain: PlainConfig = serde_yaml::from_str(yaml).unwrap();
+        ProjectionConfig::try_from(plain).unwrap()
+    }
+
+    const MINIMAL: &str = "
+mime_types: [audio/flac]
+name_mapping: ogg
+projection_command: cp {input} {output}
+";
+
+    #[test]
+    fn replace_paths_keeps_non_utf8_paths() {
+        let input = OsStr::from_bytes(b"/music/caf\xe9.flac");
+        let output = OsStr::from_bytes(b"/cache/caf\xe9.ogg");
+        let replaced = replace_paths("{input}:{output}", input, output);
+        assert_eq!(
+            replaced.as_bytes(),
+            b"/music/caf\xe9.flac:/cache/caf\xe9.ogg"
+        );
+        assert_eq!(replace_paths("-vn", input, output), "-vn");
+    }
+
+    #[test]
+    fn exclude_applies_to_the_entries_under_an_excluded_directory() {
+        let config = parse(&format!("{}exclude: ['^private$', '\\.tmp$']", MINIMAL));
+        assert!(config.is_excluded(Path::new("private")));
+        assert!(config.is_excluded(Path::new("private/album/song.flac")));
+        assert!(config.is_excluded(Path::new("album/song.tmp")));
+        assert!(!config.is_excluded(Path::new("public/private.flac")));
+        assert!(!config.is_excluded(Path::new("album/song.flac")));
+    }
+}
@@ -1,13 +1,15 @@
-use std::ffi::OsString;
-use std::fs::File;
-use std::io::{self, Read, Seek, SeekFrom};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::os::unix::io::{FromRawFd, IntoRawFd};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 use crate::libc_bridge as br;
-use crate::libc_bridge::libc::c_int;
+use crate::libc_bridge::libc::{self, c_int};
 use crate::libc_bridge::libc_wrappers;
 use fuse_mt::FileAttr;
+use seahash::SeaHasher;
 
 pub fn real_path(target: &OsString, partial: &Path) -> OsString {
     PathBuf::from(target)
@@ -15,6 +17,84 @@ pub fn real_path(target: &OsString, partial: &Path) -> OsString {
         .into_os_string()
 }
 
+/// Appends `suffix` to the file name of `path` (e.g. `a/b.flac` + `.json` -> `a/b.flac.json`)
+pub fn append_suffix<T: AsRef<Path>>(path: T, suffix: &str) -> PathBuf {
+    let mut path = path.as_ref().as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// The prefix of the name of a file being generated, until it's complete.
+/// It's a prefix so the extension (which some commands rely on) is kept.
+const TEMP_PREFIX: &str = ".projfs-tmp.";
+
+/// The path at which the file `path` is written before being complete
+pub fn temp_path(path: &OsString) -> OsString {
+    let path = Path::new(path);
+    let mut name = OsString::from(TEMP_PREFIX);
+    name.push(path.file_name().unwrap());
+    path.with_file_name(name).into_os_string()
+}
+
+/// The path of the file `path` is written for, if it's a temporary file (see `temp_path()`)
+pub fn temp_target(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    if name.starts_with(TEMP_PREFIX) && name.len() > TEMP_PREFIX.len() {
+        Some(path.with_file_name(&name[TEMP_PREFIX.len()..]))
+    } else {
+        None
+    }
+}
+
+/// Flushes the complete temporary file `temp` to the disk, and renames it to `path`, replacing any existing file
+pub fn persist(temp: &OsString, path: &OsString) -> io::Result<()> {
+    File::open(temp)?.sync_all()?;
+    fs::rename(temp, path)?;
+    if let Some(parent) = Path::new(path).parent() {
+        // Makes the renaming durable as well
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Moves the file or directory `from` to `to` (creating the parent directories), or removes it if `to` is `None`.
+/// Nothing is done if `from` doesn't exist.
+pub fn relocate(from: OsString, to: Option<OsString>) -> io::Result<()> {
+    let from = Path::new(&from);
+    let metadata = match fs::symlink_metadata(from) {
+        Ok(metadata) => metadata,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    match to {
+        Some(to) => {
+            let to = Path::new(&to);
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(from, to)
+        }
+        None if metadata.is_dir() => fs::remove_dir_all(from),
+        None => fs::remove_file(from),
+    }
+}
+
+/// Hashes the content of the file `path`
+pub fn hash_content(path: &OsString) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut hasher = SeaHasher::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.write(&buf[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(hasher.finish())
+}
+
 pub fn getattr(path: OsString) -> Result<FileAttr, c_int> {
     match libc_wrappers::lstat(path) {
         Ok(stat) => Ok(br::stat_to_fuse(stat)),
@@ -22,6 +102,89 @@ pub fn getattr(path: OsString) -> Result<FileAttr, c_int> {
     }
 }
 
+/// Same as `getattr()`, but follows symbolic links
+pub fn stat(path: OsString) -> Result<FileAttr, c_int> {
+    match libc_wrappers::stat(path) {
+        Ok(stat) => Ok(br::stat_to_fuse(stat)),
+        Err(e) => Err(e),
+    }
+}
+
+/// Sets the extended attribute `name` of `path` to `value` (without following symbolic links)
+pub fn setxattr(path: OsString, name: &OsStr, value: &[u8]) -> Result<(), c_int> {
+    libc_wrappers::lsetxattr(path, name.to_os_string(), value, 0, 0)
+}
+
+/// The value of the extended attribute `name` of `path` (without following symbolic links)
+pub fn getxattr(path: OsString, name: &OsStr) -> Result<Vec<u8>, c_int> {
+    loop {
+        let size = libc_wrappers::lgetxattr(path.clone(), name.to_os_string(), &mut [])?;
+        let mut buf = vec![0u8; size];
+        match libc_wrappers::lgetxattr(path.clone(), name.to_os_string(), &mut buf) {
+            Ok(size) => {
+                buf.truncate(size);
+                return Ok(buf);
+            }
+            // Grown in between
+            Err(libc::ERANGE) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// The names of the extended attributes of `path` (without following symbolic links), each followed by a NUL byte
+pub fn listxattr(path: OsString) -> Result<Vec<u8>, c_int> {
+    loop {
+        let size = libc_wrappers::llistxattr(path.clone(), &mut [])?;
+        let mut buf = vec![0u8; size];
+        match libc_wrappers::llistxattr(path.clone(), &mut buf) {
+            Ok(size) => {
+                buf.truncate(size);
+                return Ok(buf);
+            }
+            Err(libc::ERANGE) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Resolves the `.` and `..` components of the absolute `path` lexically.
+/// Returns `None` if it goes above the root.
+pub fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => (),
+        }
+    }
+    Some(normalized)
+}
+
+/// The relative path leading from the directory `from` to `to` (both are absolute and normalized)
+pub fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from
+        .iter()
+        .zip(to.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = PathBuf::new();
+    for _ in common..from.len() {
+        relative.push("..");
+    }
+    for component in &to[common..] {
+        relative.push(component.as_os_str());
+    }
+    relative
+}
+
 /// Test if the content of the `file` is newer than the `target`.
 /// This functions checks the file modification time.
 pub fn is_content_newer(target: OsString, file: OsString) -> Result<bool, c_int> {
@@ -63,3 +226,43 @@ impl Seek for UnmanagedFile {
         self.inner.as_ref().unwrap().seek(pos)
     }
 }
+
+impl Write for UnmanagedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.as_ref().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.as_ref().unwrap().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_resolves_dots_lexically() {
+        assert_eq!(
+            normalize(Path::new("/a/./b/../c.flac")),
+            Some(PathBuf::from("/a/c.flac"))
+        );
+        assert_eq!(normalize(Path::new("/a/..")), Some(PathBuf::from("/")));
+        assert_eq!(normalize(Path::new("/a/../..")), None);
+    }
+
+    #[test]
+    fn relative_path_goes_up_to_the_common_directory() {
+        assert_eq!(
+            relative_path(Path::new("/a/b"), Path::new("/a/c/d.ogg")),
+            PathBuf::from("../c/d.ogg")
+        );
+        assert_eq!(
+            relative_path(Path::new("/a"), Path::new("/a/b.ogg")),
+            PathBuf::from("b.ogg")
+        );
+        assert_eq!(
+            relative_path(Path::new("/a/b"), Path::new("/c.ogg")),
+            PathBuf::from("../../c.ogg")
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use fuse_mt::FileAttr;
+use serde::{Deserialize, Serialize};
+
+use crate::projfs::AccessType;
+
+/// The name of the index file, at the root of the cache directory
+const INDEX_FILE: &str = ".projfs-index.yml";
+/// The index is written to this file first, then renamed to `INDEX_FILE`
+const INDEX_TEMP_FILE: &str = ".projfs-index.yml.tmp";
+/// The delay between two saves of the index in the background, when it has changed
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What a cache file has been generated from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// The relative partial path of the source file
+    pub source: PathBuf,
+    pub access_type: AccessType,
+    /// The modification time (seconds and nanoseconds) of the source file when the generation started
+    pub source_mtime: (i64, i32),
+    /// The size of the source file when the generation started
+    pub source_size: u64,
+    /// The fingerprint of the rule which generated the file
+    pub fingerprint: u64,
+}
+
+impl Entry {
+    /// Test if the source file still has the recorded attributes
+    pub fn matches(&self, source_stat: &FileAttr) -> bool {
+        self.source_mtime == (source_stat.mtime.sec, source_stat.mtime.nsec)
+            && self.source_size == source_stat.size
+    }
+}
+
+/// The hash of the content of a source file, valid while the file keeps the same inode, modification time and size
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ContentHash {
+    /// `0` in the indexes written before it was recorded, which makes the hash be computed again
+    #[serde(default)]
+    ino: u64,
+    mtime: (i64, i32),
+    size: u64,
+    hash: u64,
+}
+
+impl ContentHash {
+    fn new(source_metadata: &fs::Metadata, hash: u64) -> ContentHash {
+        ContentHash {
+            ino: source_metadata.ino(),
+            mtime: (source_metadata.mtime(), source_metadata.mtime_nsec() as i32),
+            size: source_metadata.size(),
+            hash: hash,
+        }
+    }
+}
+
+/// The content of the index file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct IndexFile {
+    /// By the relative partial paths of the cache files
+    entries: HashMap<PathBuf, Entry>,
+    /// By the relative partial paths of the source files
+    #[serde(default)]
+    hashes: HashMap<PathBuf, ContentHash>,
+}
+
+/// The records of the files in the cache, kept in the cache directory across mounts.
+/// All the paths received are real paths; the records are keyed by the relative partial paths of the cache files.
+pub struct Index {
+    source_dir: OsString,
+    cache_dir: OsString,
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+    hashes: Mutex<HashMap<PathBuf, ContentHash>>,
+    /// Whether the index has changed since it was saved last time
+    changed: AtomicBool,
+    /// Held while saving, so that only one save writes the temporary file at a time
+    saving: Mutex<()>,
+}
+
+impl Index {
+    pub fn new(source_dir: OsString, cache_dir: OsString) -> Index {
+        Index {
+            source_dir: source_dir,
+            cache_dir: cache_dir,
+            entries: Mutex::new(HashMap::new()),
+            hashes: Mutex::new(HashMap::new()),
+            changed: AtomicBool::new(false),
+            saving: Mutex::new(()),
+        }
+    }
+
+    /// Test if `partial` (in the cache directory) is a file of the index itself
+    pub fn is_index_file(partial: &Path) -> bool {
+        partial == Path::new("/").join(INDEX_FILE)
+            || partial == Path::new("/").join(INDEX_TEMP_FILE)
+    }
+
+    /// Loads the index saved in the cache directory (if any), and returns its entries by the relative partial paths of the cache files
+    pub fn load(&self) -> Vec<(PathBuf, Entry)> {
+        let path = Path::new(&self.cache_dir).join(INDEX_FILE);
+        let f = match File::open(&path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Vec::new(),
+            Err(e) => {
+                error!("can't open the index {:?}: {}", path, e);
+                return Vec::new();
+            }
+        };
+        let loaded: IndexFile = match serde_yaml::from_reader(f) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                error!("can't read the index {:?}: {}", path, e);
+                return Vec::new();
+            }
+        };
+        info!("{} entries loaded from the index", loaded.entries.len());
+        self.hashes.lock().unwrap().extend(loaded.hashes);
+        let mut entries = self.entries.lock().unwrap();
+        entries.extend(loaded.entries);
+        entries
+            .iter()
+            .map(|(dest, entry)| (dest.clone(), entry.clone()))
+            .collect()
+    }
+
+    /// Writes the index to the cache directory, if it has changed.
+    /// This writes and syncs the whole index, so it's done in the background (see `SAVE_INTERVAL`) and when unmounting, never while serving a request.
+    pub fn save(&self) {
+        let _saving = self.saving.lock().unwrap();
+        // Changes made from now on are saved next time
+        if !self.changed.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let index_file = IndexFile {
+            entries: self.entries.lock().unwrap().clone(),
+            hashes: self.hashes.lock().unwrap().clone(),
+        };
+        let path = Path::new(&self.cache_dir).join(INDEX_FILE);
+        let temp_path = Path::new(&self.cache_dir).join(INDEX_TEMP_FILE);
+        let written = fs::create_dir_all(&self.cache_dir)
+            .and_then(|_| File::create(&temp_path))
+            .and_then(|f| {
+                serde_yaml::to_writer(&f, &index_file)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                f.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp_path, &path));
+        match written {
+            Ok(()) => debug!("{} entries saved to the index", index_file.entries.len()),
+            Err(e) => {
+                error!("can't save the index {:?}: {}", path, e);
+                self.changed.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    pub fn get(&self, dest: &OsStr) -> Option<Entry> {
+        let dest = self.cache_partial(dest)?;
+        self.entries.lock().unwrap().get(&dest).cloned()
+    }
+
+    /// Records that `dest` has been generated from `source` (whose attributes were `source_stat` when starting)
+    pub fn insert(
+        &self,
+        source: &OsStr,
+        dest: &OsStr,
+        access_type: AccessType,
+        source_stat: &FileAttr,
+        fingerprint: u64,
+    ) {
+        let (source, dest) = match (self.source_partial(source), self.cache_partial(dest)) {
+            (Some(source), Some(dest)) => (source, dest),
+            _ => return,
+        };
+        let entry = Entry {
+            source: source,
+            access_type: access_type,
+            source_mtime: (source_stat.mtime.sec, source_stat.mtime.nsec),
+            source_size: source_stat.size,
+            fingerprint: fingerprint,
+        };
+        self.entries.lock().unwrap().insert(dest, entry);
+        self.changed();
+    }
+
+    pub fn remove(&self, dest: &OsStr) {
+        if let Some(dest) = self.cache_partial(dest) {
+            if self.entries.lock().unwrap().remove(&dest).is_some() {
+                self.changed();
+            }
+        }
+    }
+
+    /// Removes the entries of the cache files generated from the source file `partial`, or from anything under it
+    pub fn forget(&self, partial: &Path) {
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.len();
+        entries.retain(|_, entry| !entry.source.starts_with(partial));
+        let mut changed = entries.len() != count;
+        drop(entries);
+        let mut hashes = self.hashes.lock().unwrap();
+        let count = hashes.len();
+        hashes.retain(|source, _| !source.starts_with(partial));
+        changed |= hashes.len() != count;
+        drop(hashes);
+        if changed {
+            self.changed();
+        }
+    }
+
+    /// The hash of the content of `source`, if it's known and `source` hasn't changed since then
+    pub fn content_hash(&self, source: &OsStr, source_metadata: &fs::Metadata) -> Option<u64> {
+        let source = self.source_partial(source)?;
+        match self.hashes.lock().unwrap().get(&source) {
+            Some(hash) if *hash == ContentHash::new(source_metadata, hash.hash) => Some(hash.hash),
+            _ => None,
+        }
+    }
+
+    pub fn insert_content_hash(&self, source: &OsStr, source_metadata: &fs::Metadata, hash: u64) {
+        if let Some(source) = self.source_partial(source) {
+            let hash = ContentHash::new(source_metadata, hash);
+            self.hashes.lock().unwrap().insert(source, hash);
+            self.changed();
+        }
+    }
+
+    /// Marks the index as changed, to be saved
+    fn changed(&self) {
+        self.changed.store(true, Ordering::SeqCst);
+    }
+
+    fn source_partial(&self, source: &OsStr) -> Option<PathBuf> {
+        partial_of(&self.source_dir, source)
+    }
+
+    fn cache_partial(&self, dest: &OsStr) -> Option<PathBuf> {
+        partial_of(&self.cache_dir, dest)
+    }
+}
+
+/// The reverse of `fsop::real_path()`
+fn partial_of(target: &OsStr, real: &OsStr) -> Option<PathBuf> {
+    Path::new(real)
+        .strip_prefix(target)
+        .ok()
+        .map(|relative| Path::new("/").join(relative))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsop;
+
+    #[test]
+    fn saved_entries_are_loaded_by_the_next_mount() {
+        let root = std::env::temp_dir().join(format!("projfs-index-test-{}", std::process::id()));
+        let (source_dir, cache_dir) = (root.join("source"), root.join("cache"));
+        fs::create_dir_all(&source_dir).unwrap();
+        fs::write(source_dir.join("song.flac"), b"flac").unwrap();
+        let source = source_dir.join("song.flac").into_os_string();
+        let dest = cache_dir.join("song.ogg").into_os_string();
+        let stat = fsop::stat(source.clone()).unwrap();
+
+        let index = Index::new(
+            source_dir.clone().into_os_string(),
+            cache_dir.clone().into_os_string(),
+        );
+        index.insert(&source, &dest, AccessType::Projected, &stat, 42);
+        index.insert_content_hash(&source, &fs::metadata(&source).unwrap(), 7);
+        index.save();
+        assert!(!cache_dir.join(INDEX_TEMP_FILE).exists());
+
+        let reloaded = Index::new(source_dir.into_os_string(), cache_dir.into_os_string());
+        let entries = reloaded.load();
+        assert_eq!(
+            entries,
+            vec![(PathBuf::from("/song.ogg"), index.get(&dest).unwrap())]
+        );
+        assert_eq!(reloaded.get(&dest).unwrap().fingerprint, 42);
+        assert!(reloaded.get(&dest).unwrap().matches(&stat));
+        assert_eq!(
+            reloaded.content_hash(&source, &fs::metadata(&source).unwrap()),
+            Some(7)
+        );
+        let _ = fs::remove_dir_all(&root);
+    }
+}
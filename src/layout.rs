@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use mime_guess::Mime;
+use regex::{Captures, Regex};
+use time::Timespec;
+
+/// Places files in the mount according to their metadata, following a path template
+pub struct Layout {
+    template: String,
+    placeholder: Regex,
+    extractor: Option<Vec<String>>,
+    mime_types: Option<Vec<Mime>>,
+    unknown: String,
+}
+
+impl Layout {
+    pub fn new(
+        template: String,
+        extractor: Option<String>,
+        mime_types: Option<Vec<Mime>>,
+        unknown: Option<String>,
+    ) -> Layout {
+        Layout {
+            template: template,
+            placeholder: Regex::new(r"\{([^{}]+)\}").unwrap(),
+            extractor: extractor.map(|cmd| cmd.split(" ").map(|s| s.into()).collect()),
+            mime_types: mime_types,
+            unknown: unknown.unwrap_or_else(|| "Unknown".to_string()),
+        }
+    }
+
+    /// Computes the path (without the leading `/`) where a file appears in the mount, or `None` if it keeps its own path.
+    /// parameter `source` is the real path of the source file; `partial` is the path it would appear at otherwise
+    pub fn path_of(&self, source: &OsStr, partial: &Path) -> Option<PathBuf> {
+        if let Some(mime_types) = &self.mime_types {
+            match mime_guess::from_path(partial).first() {
+                Some(mime) if crate::config::in_mime_vec(&mime, mime_types) => (),
+                _ => return None,
+            }
+        }
+        let metadata = self.metadata(source, partial);
+        let laid_out =
+            self.placeholder
+                .replace_all(&self.template, |caps: &Captures| {
+                    match metadata.get(&caps[1].to_lowercase()) {
+                        Some(value) if !value.trim().is_empty() => sanitize(value),
+                        _ => self.unknown.clone(),
+                    }
+                });
+        let mut path = PathBuf::from(laid_out.trim_start_matches('/'));
+        if let Some(extension) = partial.extension() {
+            let mut name = path.file_name()?.to_os_string();
+            name.push(".");
+            name.push(extension);
+            path.set_file_name(name);
+        }
+        Some(path)
+    }
+
+    fn metadata(&self, source: &OsStr, partial: &Path) -> HashMap<String, String> {
+        let mut metadata = HashMap::new();
+        if let Some(stem) = partial.file_stem() {
+            metadata.insert("name".to_string(), stem.to_string_lossy().into_owned());
+        }
+        if let Some(extension) = partial.extension() {
+            metadata.insert(
+                "extension".to_string(),
+                extension.to_string_lossy().into_owned(),
+            );
+        }
+        if let Ok(modified) = fs::metadata(source).and_then(|m| m.modified()) {
+            if let Ok(since_epoch) = modified.duration_since(std::time::UNIX_EPOCH) {
+                let tm = time::at(Timespec::new(since_epoch.as_secs() as i64, 0));
+                metadata.insert("mtime_year".to_string(), format!("{}", tm.tm_year + 1900));
+                metadata.insert("mtime_month".to_string(), format!("{:02}", tm.tm_mon + 1));
+                metadata.insert("mtime_day".to_string(), format!("{:02}", tm.tm_mday));
+            }
+        }
+        if let Some(extractor) = &self.extractor {
+            metadata.extend(run_extractor(extractor, source));
+        }
+        metadata
+    }
+}
+
+/// Runs the extractor command and parses its output, consisting of `key=value` lines.
+/// Keys are lower-cased, and stripped of any leading `-` and prefix ending with `:` (e.g. `TAG:artist` becomes `artist`).
+fn run_extractor(extractor: &[String], source: &OsStr) -> HashMap<String, String> {
+    let mut metadata = HashMap::new();
+    // The path is passed as-is, as it may not be valid UTF-8
+    let segments: Vec<OsString> = extractor.iter().map(|s| replace_input(s, source)).collect();
+    let output = match Command::new(&segments[0]).args(&segments[1..]).output() {
+        Ok(output) => output,
+        Err(e) => {
+            error!(
+                "failed to execute metadata extractor for {:?}: {}",
+                source, e
+            );
+            return metadata;
+        }
+    };
+    if !output.status.success() {
+        warn!(
+            "metadata extractor exited with {} for {:?}",
+            output.status, source
+        );
+    }
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some(pos) = line.find('=') {
+            let key = line[..pos].trim().trim_start_matches('-');
+            let key = key.rsplit(':').next().unwrap().to_lowercase();
+            let value = line[pos + 1..].trim();
+            if !key.is_empty() && !metadata.contains_key(&key) {
+                metadata.insert(key, value.to_string());
+            }
+        }
+    }
+    metadata
+}
+
+/// Replaces every `{input}` in the command segment `segment` by `source`
+fn replace_input(segment: &str, source: &OsStr) -> OsString {
+    let mut parts = segment.split("{input}");
+    let mut replaced = OsString::from(parts.next().unwrap_or_default());
+    for part in parts {
+        replaced.push(source);
+        replaced.push(part);
+    }
+    replaced
+}
+
+/// Makes a metadata value usable as (a part of) a file name
+fn sanitize(value: &str) -> String {
+    let value = value.trim().replace('/', "_").replace('\0', "");
+    match value.as_str() {
+        "." | ".." => value.replace('.', "_"),
+        _ => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::ffi::OsStrExt;
+
+    #[test]
+    fn replace_input_keeps_non_utf8_paths() {
+        let source = OsStr::from_bytes(b"/music/caf\xe9.flac");
+        let replaced = replace_input("--file={input}", source);
+        assert_eq!(replaced.as_bytes(), b"--file=/music/caf\xe9.flac");
+        assert_eq!(replace_input("-show_format", source), "-show_format");
+    }
+
+    #[test]
+    fn sanitize_makes_values_usable_as_names() {
+        assert_eq!(sanitize(" AC/DC "), "AC_DC");
+        assert_eq!(sanitize(".."), "__");
+    }
+}
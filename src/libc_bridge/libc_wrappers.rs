@@ -91,6 +91,138 @@ pub fn lstat(path: OsString) -> Result<libc::stat64, libc::c_int> {
     Ok(buf)
 }
 
+pub fn stat(path: OsString) -> Result<libc::stat64, libc::c_int> {
+    let path_c = into_cstring!(path, "stat");
+
+    let mut buf: libc::stat64 = unsafe { mem::zeroed() };
+    if -1 == unsafe { libc::stat64(path_c.as_ptr(), &mut buf) } {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap());
+    }
+
+    Ok(buf)
+}
+
+pub fn statvfs(path: OsString) -> Result<libc::statvfs, libc::c_int> {
+    let path_c = into_cstring!(path, "statvfs");
+
+    let mut buf: libc::statvfs = unsafe { mem::zeroed() };
+    if -1 == unsafe { libc::statvfs(path_c.as_ptr(), &mut buf) } {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap());
+    }
+
+    Ok(buf)
+}
+
+pub fn access(path: OsString, mask: libc::c_int) -> Result<(), libc::c_int> {
+    let path_c = into_cstring!(path, "access");
+
+    if -1 == unsafe { libc::access(path_c.as_ptr(), mask) } {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap());
+    }
+
+    Ok(())
+}
+
+pub fn create(path: OsString, flags: libc::c_int, mode: libc::mode_t) -> Result<u64, libc::c_int> {
+    let path_c = into_cstring!(path, "create");
+
+    let fd: libc::c_int = unsafe { libc::open(path_c.as_ptr(), flags | libc::O_CREAT | libc::O_EXCL, mode) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap());
+    }
+
+    Ok(fd as u64)
+}
+
+pub fn mkdir(path: OsString, mode: libc::mode_t) -> Result<(), libc::c_int> {
+    let path_c = into_cstring!(path, "mkdir");
+
+    if -1 == unsafe { libc::mkdir(path_c.as_ptr(), mode) } {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap());
+    }
+
+    Ok(())
+}
+
+pub fn unlink(path: OsString) -> Result<(), libc::c_int> {
+    let path_c = into_cstring!(path, "unlink");
+
+    if -1 == unsafe { libc::unlink(path_c.as_ptr()) } {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap());
+    }
+
+    Ok(())
+}
+
+pub fn rmdir(path: OsString) -> Result<(), libc::c_int> {
+    let path_c = into_cstring!(path, "rmdir");
+
+    if -1 == unsafe { libc::rmdir(path_c.as_ptr()) } {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap());
+    }
+
+    Ok(())
+}
+
+pub fn rename(from: OsString, to: OsString) -> Result<(), libc::c_int> {
+    let from_c = into_cstring!(from, "rename");
+    let to_c = into_cstring!(to, "rename");
+
+    if -1 == unsafe { libc::rename(from_c.as_ptr(), to_c.as_ptr()) } {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap());
+    }
+
+    Ok(())
+}
+
+pub fn chmod(path: OsString, mode: libc::mode_t) -> Result<(), libc::c_int> {
+    let path_c = into_cstring!(path, "chmod");
+
+    if -1 == unsafe { libc::chmod(path_c.as_ptr(), mode) } {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap());
+    }
+
+    Ok(())
+}
+
+pub fn lchown(path: OsString, uid: libc::uid_t, gid: libc::gid_t) -> Result<(), libc::c_int> {
+    let path_c = into_cstring!(path, "lchown");
+
+    if -1 == unsafe { libc::lchown(path_c.as_ptr(), uid, gid) } {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap());
+    }
+
+    Ok(())
+}
+
+pub fn truncate(path: OsString, size: libc::off64_t) -> Result<(), libc::c_int> {
+    let path_c = into_cstring!(path, "truncate");
+
+    if -1 == unsafe { libc::truncate64(path_c.as_ptr(), size) } {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap());
+    }
+
+    Ok(())
+}
+
+pub fn ftruncate(fd: u64, size: libc::off64_t) -> Result<(), libc::c_int> {
+    if -1 == unsafe { libc::ftruncate64(fd as libc::c_int, size) } {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap());
+    }
+
+    Ok(())
+}
+
+pub fn utimensat(path: OsString, times: &[libc::timespec; 2]) -> Result<(), libc::c_int> {
+    let path_c = into_cstring!(path, "utimensat");
+
+    if -1 == unsafe { libc::utimensat(libc::AT_FDCWD, path_c.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW) } {
+        return Err(io::Error::last_os_error().raw_os_error().unwrap());
+    }
+
+    Ok(())
+}
+
 pub fn fstat(fd: u64) -> Result<libc::stat64, libc::c_int> {
     let mut buf: libc::stat64 = unsafe { mem::zeroed() };
     if -1 == unsafe { libc::fstat64(fd as libc::c_int, &mut buf) } {
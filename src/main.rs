@@ -13,8 +13,14 @@ use seahash::SeaHasher;
 
 mod config;
 mod fsop;
+mod index;
+mod layout;
 mod libc_bridge;
+mod namespace;
+mod overlay;
 mod projfs;
+mod watcher;
+mod worker;
 
 fn repr_of_path<T: AsRef<Path>>(path: T) -> String {
     let path = path.as_ref();
@@ -30,7 +36,6 @@ fn main() {
     let yaml = load_yaml!("cli.yml");
     let matches = App::from_yaml(yaml).get_matches();
 
-    let mountpoint = matches.value_of_os("MOUNTPOINT").unwrap();
     let source_dir = matches.value_of_os("SOURCE_DIR").unwrap();
     let cache_dir = if let Some(cache_dir) = matches.value_of_os("cache") {
         OsString::from(cache_dir)
@@ -67,13 +72,72 @@ fn main() {
         &source_dir, &cache_dir
     );
 
+    let options = projfs::Options {
+        workers: value_t!(matches, "workers", usize).unwrap_or_else(|e| e.exit()),
+        prefetch: match matches.value_of("prefetch") {
+            Some("mount") => projfs::Prefetch::Mount,
+            _ => projfs::Prefetch::Listing,
+        },
+        watch: matches.is_present("watch"),
+        cache_layout: match matches.value_of("cache_layout") {
+            Some("content") => projfs::CacheLayout::Content,
+            _ => projfs::CacheLayout::Path,
+        },
+        symlinks: match matches.value_of("symlinks") {
+            Some("rewrite") => projfs::SymlinkMode::Rewrite,
+            Some("follow") => projfs::SymlinkMode::Follow,
+            _ => projfs::SymlinkMode::Keep,
+        },
+        statfs_with_cache: matches.is_present("statfs_with_cache"),
+        read_write: matches.is_present("read_write"),
+        projected_writes: match matches.value_of("projected_writes") {
+            Some("source") => projfs::ProjectedWrites::Source,
+            Some("cache") => projfs::ProjectedWrites::Cache,
+            _ => projfs::ProjectedWrites::Deny,
+        },
+        upper: matches.value_of_os("upper").map(OsString::from),
+        cache_max_size: matches.value_of("cache_max_size").map(|size| {
+            config::parse_size(size).unwrap_or_else(|e| {
+                println!("Invalid maximum cache size: {}", e);
+                std::process::exit(-1);
+            })
+        }),
+        gc_interval: if matches.is_present("gc_interval") {
+            Some(value_t!(matches, "gc_interval", u64).unwrap_or_else(|e| e.exit()))
+        } else {
+            None
+        },
+    };
+
     let filesystem = projfs::ProjectionFS::new(
         OsString::from(source_dir),
         OsString::from(cache_dir),
         proj_conf,
+        options,
     );
 
-    let fuse_args: Vec<&OsStr> = vec![&OsStr::new("-o"), &OsStr::new("ro,auto_unmount")];
+    if matches.is_present("gc_only") {
+        let reclaimed = filesystem.collect_garbage();
+        println!(
+            "Removed {} files ({} bytes) from the cache {:?}",
+            reclaimed.files, reclaimed.bytes, filesystem.cache_dir
+        );
+        return;
+    }
+
+    let mountpoint = matches.value_of_os("MOUNTPOINT").unwrap();
+    let threads = value_t!(matches, "threads", usize).unwrap_or_else(|e| e.exit());
+    let mount_options = if matches.is_present("read_write") || matches.is_present("upper") {
+        "rw,auto_unmount"
+    } else {
+        "ro,auto_unmount"
+    };
+    let fuse_args: Vec<&OsStr> = vec![&OsStr::new("-o"), &OsStr::new(mount_options)];
 
-    fuse_mt::mount(fuse_mt::FuseMT::new(filesystem, 1), &mountpoint, &fuse_args).unwrap();
+    fuse_mt::mount(
+        fuse_mt::FuseMT::new(filesystem, threads),
+        &mountpoint,
+        &fuse_args,
+    )
+    .unwrap();
 }
@@ -0,0 +1,254 @@
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use fuse_mt::FileType;
+
+use crate::projfs::AccessType;
+
+/// An entry of the virtual hierarchy.
+/// Every `source` is the relative partial path (with the leading `/`) in the source directory.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// A directory; `source` is `None` if it doesn't exist in the source directory (i.e. created by rewriting)
+    Directory { source: Option<PathBuf> },
+    /// Any other entry, provided as-is, through the projection of `source`, or as a sidecar file of `source`
+    File {
+        source: PathBuf,
+        kind: FileType,
+        access_type: AccessType,
+    },
+}
+
+/// The directory hierarchy appearing at the mount point, when it differs from the one of the source directory.
+/// All paths are the partial paths (with the leading `/`) as received from FUSE.
+pub struct Namespace {
+    nodes: RwLock<HashMap<PathBuf, Node>>,
+    children: RwLock<HashMap<PathBuf, BTreeSet<OsString>>>,
+}
+
+impl Namespace {
+    pub fn new() -> Namespace {
+        let root = PathBuf::from("/");
+        let mut nodes = HashMap::new();
+        nodes.insert(
+            root.clone(),
+            Node::Directory {
+                source: Some(root.clone()),
+            },
+        );
+        let mut children = HashMap::new();
+        children.insert(root, BTreeSet::new());
+        Namespace {
+            nodes: RwLock::new(nodes),
+            children: RwLock::new(children),
+        }
+    }
+
+    pub fn lookup<T: AsRef<Path>>(&self, path: T) -> Option<Node> {
+        self.nodes.read().unwrap().get(path.as_ref()).cloned()
+    }
+
+    /// Lists the names and nodes directly under the directory `path`
+    pub fn children<T: AsRef<Path>>(&self, path: T) -> Option<Vec<(OsString, Node)>> {
+        let path = path.as_ref();
+        let nodes = self.nodes.read().unwrap();
+        let children = self.children.read().unwrap();
+        children.get(path).map(|names| {
+            names
+                .iter()
+                .filter_map(|name| {
+                    nodes
+                        .get(&path.join(name))
+                        .map(|node| (name.clone(), node.clone()))
+                })
+                .collect()
+        })
+    }
+
+    /// Inserts `node` at `path`, creating the missing parent directories.
+    /// Returns `false` (and keeps the existing node) if something else already occupies `path`.
+    /// Inserting the same node again has no effect.
+    pub fn insert(&self, path: PathBuf, node: Node) -> bool {
+        let mut nodes = self.nodes.write().unwrap();
+        let mut children = self.children.write().unwrap();
+        if let Some(existing) = nodes.get_mut(&path) {
+            return match (existing, node) {
+                (Node::Directory { source: existing }, Node::Directory { source }) => {
+                    if existing.is_none() {
+                        *existing = source;
+                    }
+                    true
+                }
+                (existing, node) if *existing == node => true,
+                (existing, node) => {
+                    warn!(
+                        "{:?} is already occupied by {:?}; ignoring {:?}",
+                        path, existing, node
+                    );
+                    false
+                }
+            };
+        }
+        for ancestor in path.ancestors().skip(1) {
+            match nodes.get(ancestor) {
+                Some(Node::Directory { .. }) => break,
+                Some(other) => {
+                    warn!(
+                        "{:?} is not a directory ({:?}); ignoring {:?}",
+                        ancestor, other, path
+                    );
+                    return false;
+                }
+                None => (),
+            }
+        }
+        let mut current = path.as_path();
+        while let Some(parent) = current.parent() {
+            children
+                .entry(parent.to_owned())
+                .or_default()
+                .insert(current.file_name().unwrap().to_owned());
+            if nodes.contains_key(parent) {
+                break;
+            }
+            nodes.insert(parent.to_owned(), Node::Directory { source: None });
+            current = parent;
+        }
+        if let Node::Directory { .. } = node {
+            children.entry(path.clone()).or_default();
+        }
+        nodes.insert(path, node);
+        true
+    }
+
+    /// Removes the entries coming from `source` or from anything under it.
+    /// The directories which are left empty and don't exist in the source directory are removed as well.
+    pub fn remove_source(&self, source: &Path) {
+        let mut nodes = self.nodes.write().unwrap();
+        let mut children = self.children.write().unwrap();
+        let mut files = Vec::new();
+        let mut dirs = Vec::new();
+        for (path, node) in nodes.iter_mut() {
+            match node {
+                Node::File { source: origin, .. } if origin.starts_with(source) => {
+                    files.push(path.clone())
+                }
+                Node::Directory {
+                    source: Some(origin),
+                } if origin.starts_with(source) && path != Path::new("/") => {
+                    dirs.push(path.clone())
+                }
+                _ => (),
+            }
+        }
+        for path in files {
+            nodes.remove(&path);
+            unlink(&mut children, &path);
+            dirs.extend(path.parent().map(Path::to_owned));
+        }
+        for path in &dirs {
+            if let Some(Node::Directory { source }) = nodes.get_mut(path) {
+                *source = None;
+            }
+        }
+        while let Some(path) = dirs.pop() {
+            let is_empty = match children.get(&path) {
+                Some(names) => names.is_empty(),
+                None => true,
+            };
+            match nodes.get(&path) {
+                Some(Node::Directory { source: None }) if is_empty && path != Path::new("/") => {
+                    nodes.remove(&path);
+                    children.remove(&path);
+                    unlink(&mut children, &path);
+                    dirs.extend(path.parent().map(Path::to_owned));
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Records `source` as the origin of the directory `path`, if it has none yet.
+    pub fn attach(&self, path: &Path, source: PathBuf) {
+        if let Some(Node::Directory { source: existing }) =
+            self.nodes.write().unwrap().get_mut(path)
+        {
+            if existing.is_none() {
+                *existing = Some(source);
+            }
+        }
+    }
+}
+
+/// Removes `path` from the children of its parent
+fn unlink(children: &mut HashMap<PathBuf, BTreeSet<OsString>>, path: &Path) {
+    if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+        if let Some(names) = children.get_mut(parent) {
+            names.remove(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(source: &str) -> Node {
+        Node::File {
+            source: PathBuf::from(source),
+            kind: FileType::RegularFile,
+            access_type: AccessType::Projected,
+        }
+    }
+
+    #[test]
+    fn insert_creates_the_missing_directories() {
+        let namespace = Namespace::new();
+        assert!(namespace.insert(PathBuf::from("/Artist/Album/song.ogg"), file("/x.flac")));
+        assert_eq!(
+            namespace.lookup("/Artist/Album/song.ogg"),
+            Some(file("/x.flac"))
+        );
+        assert_eq!(
+            namespace.lookup("/Artist"),
+            Some(Node::Directory { source: None })
+        );
+        assert_eq!(
+            namespace.children("/"),
+            Some(vec![(
+                OsString::from("Artist"),
+                Node::Directory { source: None }
+            )])
+        );
+        assert_eq!(
+            namespace.children("/Artist/Album"),
+            Some(vec![(OsString::from("song.ogg"), file("/x.flac"))])
+        );
+        assert_eq!(namespace.lookup("/Artist/song.ogg"), None);
+    }
+
+    #[test]
+    fn insert_keeps_the_existing_node() {
+        let namespace = Namespace::new();
+        assert!(namespace.insert(PathBuf::from("/a.ogg"), file("/a.flac")));
+        assert!(namespace.insert(PathBuf::from("/a.ogg"), file("/a.flac")));
+        assert!(!namespace.insert(PathBuf::from("/a.ogg"), file("/b.flac")));
+        assert!(!namespace.insert(PathBuf::from("/a.ogg/c.ogg"), file("/c.flac")));
+        assert_eq!(namespace.lookup("/a.ogg"), Some(file("/a.flac")));
+        assert_eq!(namespace.lookup("/a.ogg/c.ogg"), None);
+    }
+
+    #[test]
+    fn directories_get_the_first_source_inserted() {
+        let namespace = Namespace::new();
+        namespace.insert(PathBuf::from("/dir/a.ogg"), file("/a.flac"));
+        let directory = |source: &str| Node::Directory {
+            source: Some(PathBuf::from(source)),
+        };
+        assert!(namespace.insert(PathBuf::from("/dir"), directory("/one")));
+        assert!(namespace.insert(PathBuf::from("/dir"), directory("/two")));
+        assert_eq!(namespace.lookup("/dir"), Some(directory("/one")));
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use fuse_mt::FileType;
+
+use crate::fsop;
+
+/// The prefix of the names of the files marking deleted entries (whiteouts), followed by the hash of the name of the entry
+/// (rather than the name itself, which may already be as long as a name can be)
+const WHITEOUT_PREFIX: &str = ".projfs-wh.";
+/// The name of the file marking a directory as opaque, i.e. hiding the content of the directory of the lower layer
+const OPAQUE_MARKER: &str = ".projfs-opaque";
+
+/// The upper directory, where the modifications made through the mount are kept instead of being made in the source directory (like overlayfs).
+/// It follows the hierarchy of the mount, and its entries shadow the ones of the projected view (the lower layer).
+/// All paths are the partial paths (with the leading `/`) as received from FUSE.
+pub struct Overlay {
+    dir: OsString,
+}
+
+impl Overlay {
+    pub fn new(dir: OsString) -> Overlay {
+        Overlay { dir: dir }
+    }
+
+    /// The real path of `partial` in the upper directory
+    pub fn real(&self, partial: &Path) -> OsString {
+        fsop::real_path(&self.dir, partial)
+    }
+
+    /// Whether `partial` exists in the upper directory
+    pub fn contains(&self, partial: &Path) -> bool {
+        fs::symlink_metadata(self.real(partial)).is_ok()
+    }
+
+    /// Whether the entry `partial` of the lower layer is deleted, i.e. it or one of its parents is whited out, or one of its parents is opaque
+    pub fn hides(&self, partial: &Path) -> bool {
+        let mut current = partial;
+        while let (Some(parent), Some(name)) = (current.parent(), current.file_name()) {
+            let upper_parent = PathBuf::from(self.real(parent));
+            if upper_parent.join(whiteout_name(name)).exists()
+                || upper_parent.join(OPAQUE_MARKER).exists()
+            {
+                return true;
+            }
+            current = parent;
+        }
+        false
+    }
+
+    /// Whether the directory `partial` hides the content of the directory of the lower layer
+    pub fn is_opaque(&self, partial: &Path) -> bool {
+        Path::new(&self.real(partial)).join(OPAQUE_MARKER).exists() || self.hides(partial)
+    }
+
+    /// Creates the missing parent directories of `partial` in the upper directory
+    pub fn create_parents(&self, partial: &Path) -> io::Result<()> {
+        match Path::new(&self.real(partial)).parent() {
+            Some(parent) => fs::create_dir_all(parent),
+            None => Ok(()),
+        }
+    }
+
+    /// Marks the entry `partial` of the lower layer as deleted
+    pub fn whiteout(&self, partial: &Path) -> io::Result<()> {
+        self.create_parents(partial)?;
+        File::create(self.whiteout_path(partial)).map(|_| ())
+    }
+
+    /// Removes the mark of the deleted entry `partial`, if any. Returns whether there was one.
+    pub fn remove_whiteout(&self, partial: &Path) -> io::Result<bool> {
+        match fs::remove_file(self.whiteout_path(partial)) {
+            Ok(()) => Ok(true),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Marks the directory `partial` of the upper directory as opaque
+    pub fn make_opaque(&self, partial: &Path) -> io::Result<()> {
+        File::create(Path::new(&self.real(partial)).join(OPAQUE_MARKER)).map(|_| ())
+    }
+
+    /// Lists the entries of the directory `partial` of the upper directory (without the marks), and the names whited out in it
+    pub fn list(&self, partial: &Path) -> (Vec<(OsString, FileType)>, Whiteouts) {
+        let mut entries = Vec::new();
+        let mut whiteouts = Whiteouts(HashSet::new());
+        let dir_entries = match fs::read_dir(self.real(partial)) {
+            Ok(dir_entries) => dir_entries,
+            Err(_) => return (entries, whiteouts),
+        };
+        for entry in dir_entries.filter_map(Result::ok) {
+            let name = entry.file_name();
+            if let Some(hash) = name.as_bytes().strip_prefix(WHITEOUT_PREFIX.as_bytes()) {
+                match std::str::from_utf8(hash).map(|hash| u64::from_str_radix(hash, 16)) {
+                    Ok(Ok(hash)) => {
+                        whiteouts.0.insert(hash);
+                    }
+                    _ => warn!("unexpected whiteout {:?} in {:?}", name, partial),
+                }
+                continue;
+            }
+            if Overlay::is_reserved(&name) {
+                continue;
+            }
+            if let Ok(stat) = fsop::getattr(entry.path().into_os_string()) {
+                entries.push((name, stat.kind));
+            }
+        }
+        (entries, whiteouts)
+    }
+
+    /// Whether `name` is reserved for the marks, so no entry can have it
+    pub fn is_reserved(name: &OsStr) -> bool {
+        name.as_bytes().starts_with(WHITEOUT_PREFIX.as_bytes()) || name == OPAQUE_MARKER
+    }
+
+    fn whiteout_path(&self, partial: &Path) -> PathBuf {
+        let name = partial.file_name().unwrap_or_default();
+        Path::new(&self.real(partial)).with_file_name(whiteout_name(name))
+    }
+}
+
+/// The names whited out in a directory of the upper directory
+pub struct Whiteouts(HashSet<u64>);
+
+impl Whiteouts {
+    pub fn contains(&self, name: &OsStr) -> bool {
+        self.0.contains(&seahash::hash(name.as_bytes()))
+    }
+}
+
+fn whiteout_name(name: &OsStr) -> OsString {
+    OsString::from(format!(
+        "{}{:016x}",
+        WHITEOUT_PREFIX,
+        seahash::hash(name.as_bytes())
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An upper directory in a temporary directory, removed when dropped
+    struct TestOverlay {
+        overlay: Overlay,
+    }
+
+    impl TestOverlay {
+        fn new(name: &str) -> TestOverlay {
+            let dir = std::env::temp_dir().join(format!(
+                "projfs-overlay-{}-{}",
+                std::process::id(),
+                name
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            TestOverlay {
+                overlay: Overlay::new(dir.into_os_string()),
+            }
+        }
+    }
+
+    impl Drop for TestOverlay {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.overlay.dir);
+        }
+    }
+
+    #[test]
+    fn whiteouts_hide_the_entry_and_its_content() {
+        let test = TestOverlay::new("whiteouts");
+        let overlay = &test.overlay;
+        // As long as a name can be
+        let long = "a".repeat(255);
+        for name in &["song.ogg", &long] {
+            let partial = Path::new("/Album").join(name);
+            assert!(!overlay.hides(&partial));
+            overlay.whiteout(&partial).unwrap();
+            assert!(overlay.hides(&partial));
+            assert!(overlay.hides(&partial.join("cover.jpg")));
+            assert!(overlay.is_opaque(&partial));
+            assert!(!overlay.hides(Path::new("/Album/other.ogg")));
+        }
+
+        let (entries, whiteouts) = overlay.list(Path::new("/Album"));
+        assert!(entries.is_empty());
+        assert!(whiteouts.contains(OsStr::new("song.ogg")));
+        assert!(whiteouts.contains(OsStr::new(&long)));
+        assert!(!whiteouts.contains(OsStr::new("other.ogg")));
+
+        let song = Path::new("/Album/song.ogg");
+        assert!(overlay.remove_whiteout(song).unwrap());
+        assert!(!overlay.remove_whiteout(song).unwrap());
+        assert!(!overlay.hides(song));
+        assert!(overlay.hides(Path::new(&format!("/Album/{}", long))));
+    }
+
+    #[test]
+    fn opaque_directories_hide_the_lower_content() {
+        let test = TestOverlay::new("opaque");
+        let overlay = &test.overlay;
+        fs::create_dir_all(overlay.real(Path::new("/Album/Disc 1"))).unwrap();
+        fs::write(overlay.real(Path::new("/Album/song.ogg")), b"").unwrap();
+        assert!(!overlay.is_opaque(Path::new("/Album")));
+
+        overlay.make_opaque(Path::new("/Album")).unwrap();
+        assert!(overlay.is_opaque(Path::new("/Album")));
+        assert!(overlay.is_opaque(Path::new("/Album/Disc 1")));
+        assert!(overlay.hides(Path::new("/Album/cover.jpg")));
+        assert!(!overlay.hides(Path::new("/Album")));
+
+        // The marks aren't entries
+        let (mut entries, _) = overlay.list(Path::new("/Album"));
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            entries,
+            vec![
+                (OsString::from("Disc 1"), FileType::Directory),
+                (OsString::from("song.ogg"), FileType::RegularFile),
+            ]
+        );
+        assert!(Overlay::is_reserved(OsStr::new(OPAQUE_MARKER)));
+        assert!(Overlay::is_reserved(&whiteout_name(OsStr::new("song.ogg"))));
+        assert!(!Overlay::is_reserved(OsStr::new("song.ogg")));
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::fsop;
+use crate::index::Index;
+
+use super::manager::{ProjectionManager, OBJECTS_DIR};
+use super::names::MountName;
+use super::{AccessType, CacheLayout, Directories, ProjectionFS, ProjectionResolver, Reclaimed};
+
+/// How a cache file is used, to choose the ones to evict
+pub(super) struct Usage {
+    pub(super) access_type: AccessType,
+    pub(super) size: u64,
+    pub(super) last_used: SystemTime,
+    /// The number of times it's open at the moment
+    pub(super) open: usize,
+}
+
+/// Keeps a cache file from being evicted as if it was open (see `ProjectionManager::pin()`), until dropped
+pub(super) struct PinGuard<'a> {
+    pub(super) pm: &'a ProjectionManager,
+    pub(super) dest: OsString,
+}
+
+impl<'a> PinGuard<'a> {
+    /// Leaves the file pinned once dropped, as it's now open: it's unpinned when closed
+    pub(super) fn keep(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl<'a> Drop for PinGuard<'a> {
+    fn drop(&mut self) {
+        self.pm.closed(&self.dest);
+    }
+}
+
+impl ProjectionManager {
+    /// Whether the size of the cache is limited, in total or for any type of files
+    pub(super) fn limits_size(&self) -> bool {
+        self.max_size.is_some()
+            || self.spec.quota().is_some()
+            || self
+                .spec
+                .sidecars()
+                .iter()
+                .any(|sidecar| sidecar.quota.is_some())
+    }
+
+    pub(super) fn quota(&self, access_type: &AccessType) -> Option<u64> {
+        match access_type {
+            AccessType::Projected => self.spec.quota(),
+            AccessType::Sidecar(index) => self.spec.sidecars()[*index].quota,
+            AccessType::PassThrough => None,
+        }
+    }
+
+    /// Records a use of the cache file `dest` (real path) of type `access_type`, which is being opened if `open`.
+    /// Evicts other files if the cache has grown too large.
+    pub(super) fn touch(&self, dest: &OsString, access_type: &AccessType, open: bool) {
+        if !self.limits_size() {
+            return;
+        }
+        let size = fs::metadata(dest).map(|m| m.len()).unwrap_or(0);
+        let mut usage = self.usage.lock().unwrap();
+        let entry = usage.entry(dest.clone()).or_insert_with(|| Usage {
+            access_type: access_type.clone(),
+            size: 0,
+            last_used: SystemTime::now(),
+            open: 0,
+        });
+        entry.size = size;
+        entry.last_used = SystemTime::now();
+        if open {
+            entry.open += 1;
+        }
+        self.evict(&mut usage);
+    }
+
+    /// Keeps the cache file `dest` (real path) of type `access_type` from being evicted until the returned guard is dropped,
+    /// e.g. from its generation until it's opened
+    pub(super) fn pin(&self, dest: &OsString, access_type: &AccessType) -> PinGuard<'_> {
+        self.touch(dest, access_type, true);
+        PinGuard {
+            pm: self,
+            dest: dest.clone(),
+        }
+    }
+
+    /// Records that the cache file `dest` (real path) has been closed
+    pub(super) fn closed(&self, dest: &OsString) {
+        if let Some(entry) = self.usage.lock().unwrap().get_mut(dest) {
+            entry.open = entry.open.saturating_sub(1);
+            entry.last_used = SystemTime::now();
+        }
+    }
+
+    /// Records the files already in the cache (e.g. from a previous mount), if its size is limited
+    pub(super) fn scan_usage(&self, resolver: &dyn ProjectionResolver) {
+        if !self.limits_size() {
+            return;
+        }
+        // Not holding the lock while walking the cache, which would hold up every file being opened
+        let mut found = HashMap::new();
+        let mut dirs = vec![PathBuf::from("/")];
+        while let Some(dir) = dirs.pop() {
+            let entries = match fs::read_dir(resolver.cache(&dir)) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.filter_map(Result::ok) {
+                let partial = dir.join(entry.file_name());
+                if Index::is_index_file(&partial) || fsop::temp_target(&partial).is_some() {
+                    continue;
+                }
+                let metadata = match entry.metadata() {
+                    Ok(metadata) => metadata,
+                    Err(_) => continue,
+                };
+                if metadata.is_dir() {
+                    dirs.push(partial);
+                    continue;
+                }
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                let access_type = match self
+                    .spec
+                    .sidecars()
+                    .iter()
+                    .position(|sidecar| name.ends_with(&sidecar.suffix))
+                {
+                    Some(index) => AccessType::Sidecar(index),
+                    None => AccessType::Projected,
+                };
+                found.insert(
+                    entry.path().into_os_string(),
+                    Usage {
+                        access_type: access_type,
+                        size: metadata.len(),
+                        last_used: metadata
+                            .accessed()
+                            .or_else(|_| metadata.modified())
+                            .unwrap_or_else(|_| SystemTime::now()),
+                        open: 0,
+                    },
+                );
+            }
+        }
+        info!("{} files found in the cache", found.len());
+        let mut usage = self.usage.lock().unwrap();
+        for (dest, entry) in found {
+            // The files used meanwhile are already known better
+            usage.entry(dest).or_insert(entry);
+        }
+        self.evict(&mut usage);
+    }
+
+    /// Removes the least recently used cache files until the quotas and the maximum size of the cache are respected.
+    /// The files which are open or being generated are kept; they are generated again when opened next time.
+    pub(super) fn evict(&self, usage: &mut HashMap<OsString, Usage>) {
+        let mut total: u64 = 0;
+        let mut totals: HashMap<AccessType, u64> = HashMap::new();
+        for entry in usage.values() {
+            total += entry.size;
+            *totals.entry(entry.access_type.clone()).or_insert(0) += entry.size;
+        }
+        let exceeds = |limit: Option<u64>, size: u64| match limit {
+            Some(limit) => size > limit,
+            None => false,
+        };
+        let over_quota = totals
+            .iter()
+            .any(|(access_type, size)| exceeds(self.quota(access_type), *size));
+        if !over_quota && !exceeds(self.max_size, total) {
+            return;
+        }
+        let mut candidates: Vec<(SystemTime, OsString)> = {
+            let generating = self.generating.lock().unwrap();
+            usage
+                .iter()
+                .filter(|(dest, entry)| entry.open == 0 && !generating.contains(*dest))
+                .map(|(dest, entry)| (entry.last_used, dest.clone()))
+                .collect()
+        };
+        candidates.sort();
+        for (_, dest) in candidates {
+            let (access_type, size) = {
+                let entry = &usage[&dest];
+                (entry.access_type.clone(), entry.size)
+            };
+            if !exceeds(self.quota(&access_type), totals[&access_type])
+                && !exceeds(self.max_size, total)
+            {
+                continue;
+            }
+            self.index.remove(&dest);
+            match fs::remove_file(&dest) {
+                Ok(()) => info!("evicted {:?} ({} bytes) from the cache", dest, size),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => {
+                    warn!("can't evict {:?} from the cache: {}", dest, e);
+                    continue;
+                }
+            }
+            usage.remove(&dest);
+            total -= size;
+            *totals.get_mut(&access_type).unwrap() -= size;
+        }
+        if exceeds(self.max_size, total) {
+            warn!(
+                "the cache ({} bytes) still exceeds its maximum size, because of the files in use",
+                total
+            );
+        }
+    }
+
+    /// Removes the files in the cache which are not generated from any (existing) source file
+    pub(super) fn collect_garbage(&self, resolver: &dyn ProjectionResolver) -> Reclaimed {
+        info!("collecting garbage in the cache");
+        let mut reclaimed = Reclaimed::default();
+        self.collect_garbage_in(resolver, Path::new("/"), &mut reclaimed);
+        info!(
+            "garbage collection removed {} files ({} bytes) from the cache",
+            reclaimed.files, reclaimed.bytes
+        );
+        reclaimed
+    }
+
+    /// Collects the garbage under the directory `dir_partial` of the cache (recursively).
+    /// Returns whether the directory is empty afterwards.
+    pub(super) fn collect_garbage_in(
+        &self,
+        resolver: &dyn ProjectionResolver,
+        dir_partial: &Path,
+        reclaimed: &mut Reclaimed,
+    ) -> bool {
+        let entries = match fs::read_dir(resolver.cache(dir_partial)) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("can't list {:?} in the cache: {}", dir_partial, e);
+                return false;
+            }
+        };
+        // The names of the projected files of the source directory, computed when needed
+        let mut projected: Option<HashSet<OsString>> = None;
+        let mut is_empty = true;
+        for entry in entries.filter_map(Result::ok) {
+            let partial = dir_partial.join(entry.file_name());
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if Index::is_index_file(&partial) {
+                is_empty = false;
+                continue;
+            }
+            if metadata.is_dir() {
+                if self.collect_garbage_in(resolver, &partial, reclaimed) {
+                    match fs::remove_dir(entry.path()) {
+                        Ok(()) => continue,
+                        Err(e) => warn!("can't remove {:?} from the cache: {}", partial, e),
+                    }
+                }
+                is_empty = false;
+                continue;
+            }
+            let temp_target = fsop::temp_target(&entry.path());
+            let wanted = if temp_target.is_some() {
+                // Kept only while being generated (see below)
+                false
+            } else if self.layout == CacheLayout::Content {
+                partial.starts_with(OBJECTS_DIR)
+                    && self.is_object_in_use(resolver, &entry.path().into_os_string())
+            } else {
+                let projected =
+                    projected.get_or_insert_with(|| self.projected_names(resolver, dir_partial));
+                projected.contains(&entry.file_name())
+                    || self.is_sidecar_of_source(resolver, &partial)
+            };
+            if wanted
+                || self
+                    .generating
+                    .lock()
+                    .unwrap()
+                    .contains(temp_target.unwrap_or_else(|| entry.path()).as_os_str())
+            {
+                is_empty = false;
+                continue;
+            }
+            debug!("removing {:?} from the cache", partial);
+            self.index.remove(entry.path().as_os_str());
+            match fs::remove_file(entry.path()) {
+                Ok(()) => {
+                    reclaimed.files += 1;
+                    reclaimed.bytes += metadata.len();
+                }
+                Err(e) => {
+                    warn!("can't remove {:?} from the cache: {}", partial, e);
+                    is_empty = false;
+                }
+            }
+        }
+        is_empty
+    }
+
+    /// Lists the names of the projected files of the (visible) files in the source directory `dir_partial`
+    pub(super) fn projected_names(
+        &self,
+        resolver: &dyn ProjectionResolver,
+        dir_partial: &Path,
+    ) -> HashSet<OsString> {
+        self.with_names(dir_partial, resolver, |listed| {
+            listed
+                .names
+                .values()
+                .filter_map(|name| match name {
+                    MountName::Projected(name) => Some(name.clone()),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    /// Test if the cache file `partial` is a sidecar file of an existing source file
+    pub(super) fn is_sidecar_of_source(
+        &self,
+        resolver: &dyn ProjectionResolver,
+        partial: &Path,
+    ) -> bool {
+        let name = match partial.file_name().and_then(OsStr::to_str) {
+            Some(name) => name,
+            None => return false,
+        };
+        self.spec.sidecars().iter().any(|sidecar| {
+            if !name.ends_with(&sidecar.suffix) || name.len() == sidecar.suffix.len() {
+                return false;
+            }
+            let source = partial.with_file_name(&name[..name.len() - sidecar.suffix.len()]);
+            sidecar.applies_to(&source)
+                && !self.spec.is_excluded(source.strip_prefix("/").unwrap())
+                && Path::new(&resolver.source(&source)).is_file()
+        })
+    }
+
+    /// Test if the cache file `dest` (real path, with the content cache layout) is the current one of the source file it's recorded for in the index
+    pub(super) fn is_object_in_use(
+        &self,
+        resolver: &dyn ProjectionResolver,
+        dest: &OsString,
+    ) -> bool {
+        let entry = match self.index.get(dest) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let source = resolver.source(&entry.source);
+        if !Path::new(&source).is_file()
+            || self
+                .spec
+                .is_excluded(entry.source.strip_prefix("/").unwrap())
+        {
+            return false;
+        }
+        let applies = match &entry.access_type {
+            AccessType::Projected => self.access_type(&source) == AccessType::Projected,
+            AccessType::Sidecar(index) => match self.spec.sidecars().get(*index) {
+                Some(sidecar) => sidecar.applies_to(&entry.source),
+                None => false,
+            },
+            AccessType::PassThrough => false,
+        };
+        applies
+            && self
+                .cache_file(&entry.source, &entry.access_type, resolver, true)
+                .as_ref()
+                == Some(dest)
+    }
+
+    /// Removes the temporary files left under the directory `dir_partial` of the cache (recursively), e.g. by a crash
+    pub(super) fn remove_temp_files(&self, resolver: &dyn ProjectionResolver, dir_partial: &Path) {
+        let entries = match fs::read_dir(resolver.cache(dir_partial)) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let partial = dir_partial.join(entry.file_name());
+            if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                self.remove_temp_files(resolver, &partial);
+            } else if fsop::temp_target(&partial).is_some() {
+                info!("removing the incomplete {:?} from the cache", partial);
+                if let Err(e) = fs::remove_file(entry.path()) {
+                    warn!("can't remove {:?} from the cache: {}", partial, e);
+                }
+            }
+        }
+    }
+}
+
+impl ProjectionFS {
+    /// Removes the files in the cache whose source file is gone, or which are not generated from it anymore
+    pub fn collect_garbage(&self) -> Reclaimed {
+        let dirs = Directories {
+            source_dir: self.source_dir.clone(),
+            cache_dir: self.cache_dir.clone(),
+        };
+        let reclaimed = self.pm.collect_garbage(&dirs);
+        // Not mounted (e.g. `--gc-only`), so nothing else saves the index
+        self.pm.index.save();
+        reclaimed
+    }
+
+    /// Collects the garbage in the cache in the background, now and then every `interval` seconds (unless `0`)
+    pub(super) fn start_gc(&self, interval: u64) {
+        let pm = Arc::clone(&self.pm);
+        let dirs = Directories {
+            source_dir: self.source_dir.clone(),
+            cache_dir: self.cache_dir.clone(),
+        };
+        let spawned = thread::Builder::new()
+            .name("projfs-gc".to_string())
+            .spawn(move || loop {
+                pm.collect_garbage(&dirs);
+                if interval == 0 {
+                    break;
+                }
+                thread::sleep(Duration::from_secs(interval));
+            });
+        if let Err(e) = spawned {
+            error!("garbage collection thread can't be spawned: {}", e);
+        }
+    }
+}
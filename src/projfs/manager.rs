@@ -0,0 +1,681 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, RwLock};
+use std::time::Duration;
+
+use bimap::BiMap;
+use mime_guess;
+use seahash::SeaHasher;
+
+use crate::config::ProjectionSpecification;
+use crate::fsop;
+use crate::index::Index;
+use crate::libc_bridge::libc;
+use crate::libc_bridge::libc_wrappers;
+
+use super::cache::Usage;
+use super::names::DirectoryNames;
+use super::{AccessType, CacheLayout, ProjectionResolver};
+
+pub(super) struct ProjectionManager {
+    pub(super) projection: RwLock<BiMap<OsString, OsString>>,
+    /// The (real) cache files being generated at the moment.
+    /// Whoever needs one of them waits for this generation (through `generated`) instead of starting another one.
+    pub(super) generating: Mutex<HashSet<OsString>>,
+    /// Notified whenever a cache file finishes being generated
+    pub(super) generated: Condvar,
+    /// The source files (relative partial paths) being converted back from a modified file (see `reverse()`)
+    pub(super) reversing: Mutex<HashSet<PathBuf>>,
+    /// Notified whenever a source file finishes being converted back
+    pub(super) reversed: Condvar,
+    /// Why the last generation of the (real) cache files failed, for those whose last generation failed
+    pub(super) failures: Mutex<HashMap<OsString, String>>,
+    /// The names in the mount of the files of the source directories, by relative partial path of the directory
+    pub(super) names: Mutex<HashMap<PathBuf, DirectoryNames>>,
+    /// The files queued for background generation, but not started yet
+    pub(super) queued: Mutex<HashSet<(PathBuf, AccessType)>>,
+    /// How the (real) cache files are used, if the size of the cache is limited
+    pub(super) usage: Mutex<HashMap<OsString, Usage>>,
+    /// The maximum total size of the cache files
+    pub(super) max_size: Option<u64>,
+    pub(super) layout: CacheLayout,
+    pub(super) index: Index,
+    pub(super) spec: Box<dyn ProjectionSpecification>,
+}
+
+/// Marks a cache file as being generated, until dropped
+pub(super) struct GeneratingGuard<'a> {
+    pub(super) pm: &'a ProjectionManager,
+    pub(super) dest: &'a OsString,
+}
+
+impl<'a> Drop for GeneratingGuard<'a> {
+    fn drop(&mut self) {
+        self.pm.generating.lock().unwrap().remove(self.dest);
+        self.pm.generated.notify_all();
+    }
+}
+
+/// Marks a source file as being converted back, until dropped
+pub(super) struct ReversingGuard<'a> {
+    pub(super) pm: &'a ProjectionManager,
+    pub(super) source_partial: &'a Path,
+}
+
+impl<'a> Drop for ReversingGuard<'a> {
+    fn drop(&mut self) {
+        self.pm
+            .reversing
+            .lock()
+            .unwrap()
+            .remove(self.source_partial);
+        self.pm.reversed.notify_all();
+    }
+}
+
+/// The directory (in the cache directory) of the cache files, with the content cache layout
+pub(super) const OBJECTS_DIR: &str = "/.objects";
+
+/// Stands for the cache files of the source files not hashed yet (see `ProjectionManager::cache_file()`), and never exists
+pub(super) const UNHASHED_OBJECT: &str = "unhashed";
+
+/// The extended attribute of the cache files telling the fingerprint of the rule generating them (see `ProjectionManager::record_fingerprint()`)
+pub(super) const FINGERPRINT_XATTR: &str = "user.projfs.fingerprint";
+
+/// How long a read past the end of a file being generated waits before checking for more data
+pub(super) const STREAM_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+impl ProjectionManager {
+    pub(super) fn new(
+        spec: Box<dyn ProjectionSpecification>,
+        layout: CacheLayout,
+        max_size: Option<u64>,
+        index: Index,
+    ) -> ProjectionManager {
+        ProjectionManager {
+            projection: RwLock::new(BiMap::new()),
+            generating: Mutex::new(HashSet::new()),
+            generated: Condvar::new(),
+            reversing: Mutex::new(HashSet::new()),
+            reversed: Condvar::new(),
+            failures: Mutex::new(HashMap::new()),
+            names: Mutex::new(HashMap::new()),
+            queued: Mutex::new(HashSet::new()),
+            usage: Mutex::new(HashMap::new()),
+            max_size: max_size,
+            layout: layout,
+            index: index,
+            spec: spec,
+        }
+    }
+
+    /// Loads the index of a previous mount, which makes its projected files known without listing their directories
+    pub(super) fn load_index(&self) {
+        for (dest_partial, entry) in self.index.load() {
+            // With the content cache layout, the paths of the cache files are not those in the mount
+            if entry.access_type == AccessType::Projected && self.layout == CacheLayout::Path {
+                self.insert(entry.source.into_os_string(), dest_partial.into_os_string());
+            }
+        }
+    }
+
+    /// Tells the status of the cache file `dest` generated from `source` (both are real paths)
+    pub(super) fn status(
+        &self,
+        source: &OsString,
+        dest: &OsString,
+        access_type: &AccessType,
+    ) -> ProjectionStatus {
+        let generating = self.generating.lock().unwrap().contains(dest);
+        if generating {
+            ProjectionStatus::InProgress
+        } else if self.is_fresh(source, dest, access_type) {
+            ProjectionStatus::Done
+        } else if self.failures.lock().unwrap().contains_key(dest) {
+            ProjectionStatus::Failed
+        } else {
+            ProjectionStatus::Pending
+        }
+    }
+
+    /// Test if the cache file `dest` is up to date with `source` (both are real paths).
+    /// Only `source` and the rule generating `dest` are checked if `dest` is in the index;
+    /// otherwise `dest` is checked to be newer than `source` and to carry the fingerprint of the current rule
+    /// (see `record_fingerprint()`), and recorded in the index if so.
+    pub(super) fn is_fresh(
+        &self,
+        source: &OsString,
+        dest: &OsString,
+        access_type: &AccessType,
+    ) -> bool {
+        if self.layout == CacheLayout::Content {
+            // The name of `dest` already tells the content and the rule it's generated from
+            return Path::new(dest).exists();
+        }
+        if !Path::new(dest).exists() {
+            // Removed from the cache behind its back, or never generated
+            return false;
+        }
+        if let Some(entry) = self.index.get(dest) {
+            if entry.fingerprint != self.fingerprint(access_type) {
+                debug!("the rule generating {:?} has changed", dest);
+                return false;
+            }
+            return match fsop::stat(source.clone()) {
+                Ok(stat) => entry.matches(&stat),
+                Err(_) => false,
+            };
+        }
+        match fsop::is_content_newer(dest.clone(), source.clone()) {
+            Ok(false) if self.has_fingerprint(dest, access_type) => {
+                if let Ok(stat) = fsop::stat(source.clone()) {
+                    self.index.insert(
+                        source,
+                        dest,
+                        access_type.clone(),
+                        &stat,
+                        self.fingerprint(access_type),
+                    );
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The real path of the cache file of type `access_type` generated from the source file `source_partial`,
+    /// or `None` if it's a projected file hidden by a name collision (which is never generated).
+    /// With the content cache layout, the source file is hashed if `hash` and its hash isn't known yet;
+    /// otherwise the returned path is in `OBJECTS_DIR` but never exists, so the file is taken as not generated yet.
+    pub(super) fn cache_file(
+        &self,
+        source_partial: &Path,
+        access_type: &AccessType,
+        resolver: &dyn ProjectionResolver,
+        hash: bool,
+    ) -> Option<OsString> {
+        let dest_partial = match access_type {
+            AccessType::Sidecar(index) => {
+                fsop::append_suffix(source_partial, &self.spec.sidecars()[*index].suffix)
+            }
+            // Hidden by a name collision, so it's not generated: the name may be another file's
+            _ => self.projected_name(source_partial, resolver)?,
+        };
+        if self.layout == CacheLayout::Path {
+            return Some(resolver.cache(&dest_partial));
+        }
+        let source = resolver.source(source_partial);
+        let content_hash = match self.content_hash(&source, hash) {
+            Ok(Some(hash)) => hash,
+            Ok(None) => return Some(resolver.cache(&Path::new(OBJECTS_DIR).join(UNHASHED_OBJECT))),
+            Err(e) => {
+                // It can't be generated either; the path layout gives a meaningful name in the errors
+                warn!("can't hash the content of {:?}: {}", source, e);
+                return Some(resolver.cache(&dest_partial));
+            }
+        };
+        let mut hasher = SeaHasher::new();
+        hasher.write_u64(content_hash);
+        hasher.write_u64(self.fingerprint(access_type));
+        let extension = match access_type {
+            AccessType::Sidecar(index) => self.spec.sidecars()[*index].suffix.clone(),
+            _ => match dest_partial.extension() {
+                Some(extension) => format!(".{}", extension.to_string_lossy()),
+                None => String::new(),
+            },
+        };
+        Some(resolver.cache(&Path::new(OBJECTS_DIR).join(format!(
+            "{:016x}{}",
+            hasher.finish(),
+            extension
+        ))))
+    }
+
+    /// The hash of the content of `source` (real path), from the index if it hasn't changed since it was last hashed.
+    /// Otherwise, the file is read to compute it only if `hash`.
+    pub(super) fn content_hash(&self, source: &OsString, hash: bool) -> io::Result<Option<u64>> {
+        let metadata = fs::metadata(source)?;
+        if let Some(hash) = self.index.content_hash(source, &metadata) {
+            return Ok(Some(hash));
+        }
+        if !hash {
+            return Ok(None);
+        }
+        debug!("hashing the content of {:?}", source);
+        let hash = fsop::hash_content(source)?;
+        self.index.insert_content_hash(source, &metadata, hash);
+        Ok(Some(hash))
+    }
+
+    pub(super) fn fingerprint(&self, access_type: &AccessType) -> u64 {
+        match access_type {
+            AccessType::Sidecar(index) => self.spec.sidecars()[*index].fingerprint(),
+            _ => self.spec.fingerprint(),
+        }
+    }
+
+    /// Records the fingerprint of the rule generating the cache file `file` (real path) of type `access_type` in the file itself,
+    /// so it can be known without the index (e.g. after a crash)
+    pub(super) fn record_fingerprint(&self, file: &OsString, access_type: &AccessType) {
+        let fingerprint = format!("{:016x}", self.fingerprint(access_type));
+        if let Err(e) = fsop::setxattr(
+            file.clone(),
+            OsStr::new(FINGERPRINT_XATTR),
+            fingerprint.as_bytes(),
+        ) {
+            // It's then generated again when missing from the index
+            debug!(
+                "can't record the fingerprint of {:?}: {}",
+                file,
+                io::Error::from_raw_os_error(e)
+            );
+        }
+    }
+
+    /// Whether the cache file `file` (real path) of type `access_type` is generated by the current rule, as recorded in the file
+    pub(super) fn has_fingerprint(&self, file: &OsString, access_type: &AccessType) -> bool {
+        match fsop::getxattr(file.clone(), OsStr::new(FINGERPRINT_XATTR)) {
+            Ok(value) => value == format!("{:016x}", self.fingerprint(access_type)).as_bytes(),
+            Err(_) => false,
+        }
+    }
+
+    pub(super) fn destination(&self, filepath: &OsString) -> Option<OsString> {
+        match self.projection.read().unwrap().get_by_left(filepath) {
+            Some(dest) => Some(dest.clone()),
+            None => None,
+        }
+    }
+
+    pub(super) fn source(&self, filepath: &OsString) -> Option<OsString> {
+        match self.projection.read().unwrap().get_by_right(filepath) {
+            Some(source) => Some(source.clone()),
+            None => None,
+        }
+    }
+
+    pub(super) fn insert(&self, input: OsString, output: OsString) {
+        self.projection.write().unwrap().insert(input, output);
+    }
+
+    /// Forgets the projections of the source file `partial`, or of anything under it
+    pub(super) fn forget(&self, partial: &Path) {
+        self.projection
+            .write()
+            .unwrap()
+            .retain(|source, _| !Path::new(source).starts_with(partial));
+        self.index.forget(partial);
+    }
+
+    pub(super) fn access_type<T: AsRef<Path>>(&self, file_path: T) -> AccessType {
+        let file_path = file_path.as_ref();
+        if file_path.is_dir() {
+            error!(
+                "atype() shouldn't be called on a directory ({:?})",
+                file_path
+            );
+        }
+        let guess = mime_guess::from_path(file_path);
+        if guess.is_empty() {
+            warn!("MIME for filepath {} can't guess", file_path.display());
+            AccessType::PassThrough
+        } else {
+            let first_guess = guess.first().unwrap();
+            info!("MIME for {:?} is {}", file_path, first_guess);
+            if self.spec.should_project(&first_guess) {
+                AccessType::Projected
+            } else {
+                AccessType::PassThrough
+            }
+        }
+    }
+
+    /// parameter `partial` is the relative partial path, pointing to the *file* to be projected
+    pub(super) fn project<T: AsRef<Path>>(
+        &self,
+        partial: T,
+        resolver: &dyn ProjectionResolver,
+    ) -> Result<OsString, libc::c_int> {
+        let source_partial = partial.as_ref();
+        self.map_name(source_partial, resolver);
+        let dest = self
+            .cache_file(source_partial, &AccessType::Projected, resolver, true)
+            .ok_or(libc::ENOENT)?;
+        let source = &resolver.source(source_partial);
+        self.refresh(source, &dest, &AccessType::Projected)?;
+        Ok(dest)
+    }
+
+    /// Generates (if necessary) the sidecar file `index` of the file at `partial`, and returns the real path of the sidecar file
+    pub(super) fn generate_sidecar<T: AsRef<Path>>(
+        &self,
+        partial: T,
+        index: usize,
+        resolver: &dyn ProjectionResolver,
+    ) -> Result<OsString, libc::c_int> {
+        let source_partial = partial.as_ref();
+        let access_type = AccessType::Sidecar(index);
+        let dest = self
+            .cache_file(source_partial, &access_type, resolver, true)
+            .ok_or(libc::ENOENT)?;
+        let source = &resolver.source(source_partial);
+        self.refresh(source, &dest, &access_type)?;
+        Ok(dest)
+    }
+
+    /// Makes sure the projected or sidecar file of `source` is generated and up to date
+    pub(super) fn materialize(
+        &self,
+        source: &Path,
+        access_type: &AccessType,
+        resolver: &dyn ProjectionResolver,
+    ) -> Result<(), libc::c_int> {
+        let dest = match access_type {
+            AccessType::Projected => self.project(source, resolver)?,
+            AccessType::Sidecar(index) => self.generate_sidecar(source, *index, resolver)?,
+            AccessType::PassThrough => return Ok(()),
+        };
+        self.touch(&dest, access_type, false);
+        Ok(())
+    }
+
+    /// Same as `materialize()` for a background worker, which leaves the file alone if it's already being generated:
+    /// that generation may be another job waiting for a worker, which would never run if every worker waited for it.
+    pub(super) fn prefetch(
+        &self,
+        source_partial: &Path,
+        access_type: &AccessType,
+        resolver: &dyn ProjectionResolver,
+    ) -> Result<(), libc::c_int> {
+        match access_type {
+            AccessType::Projected => {
+                self.map_name(source_partial, resolver);
+            }
+            AccessType::Sidecar(_) => (),
+            AccessType::PassThrough => return Ok(()),
+        }
+        let dest = match self.cache_file(source_partial, access_type, resolver, true) {
+            Some(dest) => dest,
+            // Hidden by a name collision meanwhile
+            None => return Ok(()),
+        };
+        let source = resolver.source(source_partial);
+        if self.claim(&source, &dest, access_type)? {
+            self.run_generation(&source, &dest, access_type);
+        }
+        self.touch(&dest, access_type, false);
+        Ok(())
+    }
+
+    /// Generates `dest` from `source` (both are real paths) as the file of type `access_type`
+    pub(super) fn generate(
+        &self,
+        access_type: &AccessType,
+        source: &OsStr,
+        dest: &OsStr,
+    ) -> Result<(), String> {
+        match access_type {
+            AccessType::Projected => self.spec.project(source, dest),
+            AccessType::Sidecar(index) => self.spec.sidecars()[*index].generate(source, dest),
+            AccessType::PassThrough => Err("not a generated file".to_string()),
+        }
+    }
+
+    /// Whether the files of type `access_type` can be converted back into their source file (see `reverse()`)
+    pub(super) fn reversible(&self, access_type: &AccessType) -> bool {
+        match access_type {
+            AccessType::Projected => self.spec.reversible(),
+            AccessType::Sidecar(index) => self.spec.sidecars()[*index].reversible(),
+            AccessType::PassThrough => false,
+        }
+    }
+
+    /// Converts the modified projected or sidecar file `dest` (real path) back into the source file `source_partial`, if its rule can.
+    /// `dest` is then recorded as up to date with the new source file, so it's not generated again.
+    pub(super) fn reverse(
+        &self,
+        source_partial: &Path,
+        dest: &OsString,
+        access_type: &AccessType,
+        resolver: &dyn ProjectionResolver,
+    ) {
+        if !self.reversible(access_type) {
+            return;
+        }
+        // One conversion at a time into the same source file
+        let mut reversing = self.reversing.lock().unwrap();
+        while !reversing.insert(source_partial.to_owned()) {
+            reversing = self.reversed.wait(reversing).unwrap();
+        }
+        drop(reversing);
+        let _guard = ReversingGuard {
+            pm: self,
+            source_partial: source_partial,
+        };
+        let source = resolver.source(source_partial);
+        let temp = fsop::temp_path(&source);
+        let reversed = match access_type {
+            AccessType::Projected => self.spec.reverse(dest, &temp),
+            AccessType::Sidecar(index) => self.spec.sidecars()[*index].reverse(dest, &temp),
+            AccessType::PassThrough => return,
+        };
+        let reversed = reversed.and_then(|()| {
+            // The source file keeps its permissions
+            if let Ok(metadata) = fs::metadata(&source) {
+                fs::set_permissions(&temp, metadata.permissions())
+                    .map_err(|e| format!("can't set the permissions: {}", e))?;
+            }
+            fsop::persist(&temp, &source).map_err(|e| format!("can't move into place: {}", e))
+        });
+        if let Err(e) = reversed {
+            error!(
+                "{:?} hasn't been converted back into {:?}: {}",
+                dest, source, e
+            );
+            if let Err(e) = fsop::relocate(temp.clone(), None) {
+                error!("can't remove the incomplete {:?}: {}", temp, e);
+            }
+            return;
+        }
+        info!("{:?} converted back into {:?}", dest, source);
+        let stat = match fsop::stat(source.clone()) {
+            Ok(stat) => stat,
+            Err(_) => return,
+        };
+        let dest = if self.layout == CacheLayout::Content {
+            // The file is named after the new content of the source file
+            let new_dest = match self.cache_file(source_partial, access_type, resolver, true) {
+                Some(new_dest) => new_dest,
+                None => {
+                    warn!(
+                        "{:?} is hidden by a name collision meanwhile",
+                        source_partial
+                    );
+                    return;
+                }
+            };
+            if let Err(e) = fsop::relocate(dest.clone(), Some(new_dest.clone())) {
+                error!("can't move {:?} to {:?}: {}", dest, new_dest, e);
+                return;
+            }
+            new_dest
+        } else {
+            dest.clone()
+        };
+        // The cache file is not older than the new source file, which doesn't need to be projected again
+        let times = [
+            libc::timespec {
+                tv_sec: 0,
+                tv_nsec: libc::UTIME_OMIT,
+            },
+            libc::timespec {
+                tv_sec: stat.mtime.sec as libc::time_t,
+                tv_nsec: stat.mtime.nsec as libc::c_long,
+            },
+        ];
+        if let Err(e) = libc_wrappers::utimensat(dest.clone(), &times) {
+            warn!(
+                "can't set the modification time of {:?}: {}",
+                dest,
+                io::Error::from_raw_os_error(e)
+            );
+        }
+        self.record_fingerprint(&dest, access_type);
+        self.index.insert(
+            &source,
+            &dest,
+            access_type.clone(),
+            &stat,
+            self.fingerprint(access_type),
+        );
+    }
+
+    /// Waits a moment for more data to be written to `dest`.
+    /// Returns `false` immediately if `dest` is not being generated (anymore).
+    pub(super) fn wait_for_data(&self, dest: &OsString) -> bool {
+        let generating = self.generating.lock().unwrap();
+        if !generating.contains(dest) {
+            return false;
+        }
+        let _ = self
+            .generated
+            .wait_timeout(generating, STREAM_POLL_INTERVAL)
+            .unwrap();
+        true
+    }
+
+    /// Generates `dest` from `source` as the file of type `access_type`, unless `dest` is up to date.
+    /// If `dest` is being generated by someone else, waits for it instead. Fails if the generation fails.
+    pub(super) fn refresh(
+        &self,
+        source: &OsString,
+        dest: &OsString,
+        access_type: &AccessType,
+    ) -> Result<(), libc::c_int> {
+        self.wait_generated(dest);
+        if self.claim(source, dest, access_type)? {
+            self.run_generation(source, dest, access_type);
+        } else {
+            self.wait_generated(dest);
+        }
+        // The outdated `dest` may still be there
+        if self.failures.lock().unwrap().contains_key(dest) {
+            return Err(libc::EIO);
+        }
+        Ok(())
+    }
+
+    /// Waits until `dest` isn't being generated
+    pub(super) fn wait_generated(&self, dest: &OsString) {
+        let mut generating = self.generating.lock().unwrap();
+        while generating.contains(dest) {
+            debug!("waiting for {:?} being generated", dest);
+            generating = self.generated.wait(generating).unwrap();
+        }
+    }
+
+    /// Generates `dest` (claimed by `claim()`) from `source`, and records it in the index if it succeeds.
+    /// The file is written under a temporary name, and only renamed to `dest` once complete.
+    pub(super) fn run_generation(
+        &self,
+        source: &OsString,
+        dest: &OsString,
+        access_type: &AccessType,
+    ) {
+        let _guard = GeneratingGuard {
+            pm: self,
+            dest: dest,
+        };
+        let temp = fsop::temp_path(dest);
+        // Left by an interrupted generation
+        let _ = fs::remove_file(&temp);
+        let source_stat = fsop::stat(source.clone());
+        let generated = self.generate(access_type, source, &temp).and_then(|()| {
+            self.record_fingerprint(&temp, access_type);
+            fsop::persist(&temp, dest).map_err(|e| format!("can't move into place: {}", e))
+        });
+        if let Err(e) = generated {
+            error!("{:?} hasn't been generated from {:?}: {}", dest, source, e);
+            if let Err(e) = fsop::relocate(temp.clone(), None) {
+                error!("can't remove the incomplete {:?}: {}", temp, e);
+            }
+            self.failures.lock().unwrap().insert(dest.clone(), e);
+            return;
+        }
+        self.failures.lock().unwrap().remove(dest);
+        match source_stat {
+            Ok(stat) => self.index.insert(
+                source,
+                dest,
+                access_type.clone(),
+                &stat,
+                self.fingerprint(access_type),
+            ),
+            Err(_) => warn!(
+                "{:?} can't be recorded in the index without {:?}",
+                dest, source
+            ),
+        }
+    }
+
+    /// Checks whether `dest` needs to be (re)generated from `source`, and if so marks it as being generated, unless someone else does.
+    /// Returns whether it's claimed: the caller then has to generate it with `run_generation()`.
+    /// The outdated `dest` is left in place until it's replaced, so it's not lost if the generation fails.
+    /// The checks are made before taking the `generating` lock (which isn't held during any I/O), and again once claimed.
+    pub(super) fn claim(
+        &self,
+        source: &OsString,
+        dest: &OsString,
+        access_type: &AccessType,
+    ) -> Result<bool, libc::c_int> {
+        let dest_path = Path::new(dest);
+        let exists = dest_path.exists();
+        if exists && self.is_fresh(source, dest, access_type) {
+            return Ok(false);
+        }
+        if !exists {
+            let dir = dest_path.parent().unwrap_or(dest_path);
+            if let Err(e) = fs::create_dir_all(dir) {
+                error!("cache directory {:?} can't be created: {}", dir, e);
+                return Err(e.raw_os_error().unwrap_or(libc::EIO));
+            }
+        }
+        if !self.generating.lock().unwrap().insert(dest.clone()) {
+            // Claimed by someone else meanwhile
+            return Ok(false);
+        }
+        if dest_path.exists() && self.is_fresh(source, dest, access_type) {
+            // Generated by someone else between the checks and the claim
+            self.generating.lock().unwrap().remove(dest);
+            self.generated.notify_all();
+            return Ok(false);
+        }
+        self.index.remove(dest);
+        Ok(true)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum ProjectionStatus {
+    /// Not generated yet, or outdated
+    Pending,
+    InProgress,
+    Done,
+    /// The last generation failed (see `ProjectionManager::failures`)
+    Failed,
+}
+
+impl ProjectionStatus {
+    pub(super) fn name(&self) -> &'static str {
+        match self {
+            ProjectionStatus::Pending => "pending",
+            ProjectionStatus::InProgress => "in-progress",
+            ProjectionStatus::Done => "done",
+            ProjectionStatus::Failed => "failed",
+        }
+    }
+}
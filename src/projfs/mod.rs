@@ -0,0 +1,2080 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, OsStr, OsString};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use fuse_mt::*;
+use serde::{Deserialize, Serialize};
+use time::Timespec;
+
+use crate::config::{PassThroughMode, ProjectionSpecification};
+use crate::fsop::{self, UnmanagedFile};
+use crate::index::{self, Index};
+use crate::libc_bridge as br;
+use crate::libc_bridge::libc;
+use crate::libc_bridge::libc_wrappers;
+use crate::namespace::{Namespace, Node};
+use crate::overlay::Overlay;
+use crate::watcher::{self, Change};
+use crate::worker::WorkerPool;
+
+mod cache;
+mod manager;
+mod names;
+mod open;
+#[cfg(test)]
+mod tests;
+mod upper;
+mod write;
+mod xattr;
+
+use self::manager::{ProjectionManager, ProjectionStatus};
+use self::names::MountName;
+use self::open::{DeferredOpen, OpenFile, FIRST_DEFERRED_FH, FOPEN_DIRECT_IO};
+use self::upper::upper_error;
+use self::write::{Modification, PendingModification};
+use self::xattr::{xattr_reply, VIRTUAL_XATTRS, VIRTUAL_XATTR_PREFIX};
+
+const TTL: Timespec = Timespec { sec: 1, nsec: 0 };
+/// Placeholder attributes are not cached, so the real ones are seen as soon as they are available
+const PLACEHOLDER_TTL: Timespec = Timespec { sec: 0, nsec: 0 };
+
+trait ProjectionResolver {
+    fn source(&self, partial: &Path) -> OsString;
+    fn cache(&self, partial: &Path) -> OsString;
+}
+
+/// When files are projected in the background
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Prefetch {
+    /// The files of a directory are queued when the directory is listed
+    Listing,
+    /// All files are queued when mounting (and when their directory is listed)
+    Mount,
+}
+
+/// How the files in the cache are named
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheLayout {
+    /// At the path of the projected or sidecar file in the mount
+    Path,
+    /// In `OBJECTS_DIR`, by a hash of the content of the source file and of the rule generating the file
+    Content,
+}
+
+/// How the symbolic links in the source directory are presented
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymlinkMode {
+    /// As-is
+    Keep,
+    /// The links with a relative target leading to a projected file lead to the projected file instead (and are renamed the same way)
+    Rewrite,
+    /// The links to regular files appear as the files they lead to (and are projected as such)
+    Follow,
+}
+
+/// How the modifications of the projected and sidecar files are handled, when mounted read-write
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProjectedWrites {
+    /// They are rejected with `EACCES`
+    Deny,
+    /// Renaming or removing a projected file, and changing the mode, owner or times of a projected or sidecar file, apply to its source file.
+    /// The content can only be modified if the rule generating the file can convert it back (see `reverse_command`).
+    Source,
+    /// As `Source`, and the content is modified in the cache, until the file is generated again (e.g. when the source file changes).
+    /// Not with `CacheLayout::Content`, whose cache files are shared by the source files with the same content.
+    Cache,
+}
+
+/// Options of the filesystem which are not part of the projection specification
+pub struct Options {
+    /// The number of background projection workers; `0` disables background projection
+    pub workers: usize,
+    pub prefetch: Prefetch,
+    /// Whether the source directory is watched for changes, which are applied while running
+    pub watch: bool,
+    pub cache_layout: CacheLayout,
+    pub symlinks: SymlinkMode,
+    /// Whether `statfs()` adds the figures of the filesystem of the cache directory to those of the source directory
+    pub statfs_with_cache: bool,
+    /// Whether the mount can be modified, which modifies the source directory
+    pub read_write: bool,
+    pub projected_writes: ProjectedWrites,
+    /// The upper directory, keeping the modifications made through the mount instead of the source directory (see `Overlay`)
+    pub upper: Option<OsString>,
+    /// The maximum total size (in bytes) of the files in the cache
+    pub cache_max_size: Option<u64>,
+    /// Collects the garbage in the cache when mounting and then every this many seconds (`0` for only when mounting);
+    /// `None` disables the collection
+    pub gc_interval: Option<u64>,
+}
+
+/// What a garbage collection of the cache has removed
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Reclaimed {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Clones share the same state, which lets background threads (e.g. applying the changes of the source directory) use the filesystem
+#[derive(Clone)]
+pub struct ProjectionFS {
+    pub source_dir: OsString,
+    pub cache_dir: OsString,
+    pm: Arc<ProjectionManager>,
+    namespace: Option<Arc<Namespace>>,
+    overlay: Option<Arc<Overlay>>,
+    options: Arc<Options>,
+    workers: Option<Arc<WorkerPool>>,
+    /// The open projected and sidecar files, by file handle
+    open_files: Arc<Mutex<HashMap<u64, OpenFile>>>,
+    /// The source files opened for writing, by file handle (to the relative partial path)
+    written: Arc<Mutex<HashMap<u64, PathBuf>>>,
+    /// The files whose opening is deferred to their first use, by (deferred) file handle
+    deferred: Arc<Mutex<HashMap<u64, Arc<Mutex<DeferredOpen>>>>>,
+    next_deferred_fh: Arc<AtomicU64>,
+    /// Whether the source directories contain anything visible (see `prune_empty_dirs`), by relative partial path.
+    /// Forgotten when anything under them changes.
+    visible_dirs: Arc<Mutex<HashMap<PathBuf, Visibility>>>,
+    /// The files of the mount with a modification waiting for them to be generated, by path (see `truncate_later()`)
+    pending: Arc<Mutex<HashMap<PathBuf, PendingModification>>>,
+    /// Notified when a pending modification is done
+    modified: Arc<Condvar>,
+}
+
+/// Owned copy of the directories, resolving paths in background jobs
+#[derive(Clone)]
+struct Directories {
+    source_dir: OsString,
+    cache_dir: OsString,
+}
+
+impl ProjectionResolver for Directories {
+    fn source(&self, partial: &Path) -> OsString {
+        fsop::real_path(&self.source_dir, partial)
+    }
+
+    fn cache(&self, partial: &Path) -> OsString {
+        fsop::real_path(&self.cache_dir, partial)
+    }
+}
+
+/// Whether a source directory contains anything visible, as checked at its modification time
+#[derive(Clone)]
+struct Visibility {
+    mtime: Timespec,
+    visible: bool,
+    /// The subdirectories the result depends on, i.e. those checked before finding a visible entry (all of them if none)
+    subdirs: Vec<PathBuf>,
+}
+
+/// The result of resolving a path in the mount
+#[derive(Clone)]
+struct Resolved {
+    access_type: AccessType,
+    /// The relative partial path of the corresponding entry in the source directory
+    source: PathBuf,
+    /// The real path to be accessed (either in the source directory, in the cache directory or in the upper directory)
+    real: OsString,
+    /// Whether it's an entry of the upper directory (see `Overlay`), whose `source` is its path in the mount
+    upper: bool,
+}
+
+impl ProjectionFS {
+    pub fn new(
+        source_dir: OsString,
+        cache_dir: OsString,
+        conf: Box<dyn ProjectionSpecification>,
+        options: Options,
+    ) -> ProjectionFS {
+        let namespace = if conf.rewrites_paths() {
+            Some(Arc::new(Namespace::new()))
+        } else {
+            None
+        };
+        let index = Index::new(source_dir.clone(), cache_dir.clone());
+        let workers = if options.workers > 0 {
+            Some(Arc::new(WorkerPool::new(options.workers)))
+        } else {
+            None
+        };
+        let pm = ProjectionManager::new(conf, options.cache_layout, options.cache_max_size, index);
+        // Loaded even when not mounting (e.g. `--gc-only`), as the index tells which cache files are in use
+        pm.load_index();
+        ProjectionFS {
+            source_dir: source_dir,
+            cache_dir: cache_dir,
+            pm: Arc::new(pm),
+            namespace: namespace,
+            overlay: options
+                .upper
+                .clone()
+                .map(|upper| Arc::new(Overlay::new(upper))),
+            options: Arc::new(options),
+            workers: workers,
+            open_files: Arc::new(Mutex::new(HashMap::new())),
+            written: Arc::new(Mutex::new(HashMap::new())),
+            deferred: Arc::new(Mutex::new(HashMap::new())),
+            next_deferred_fh: Arc::new(AtomicU64::new(FIRST_DEFERRED_FH)),
+            visible_dirs: Arc::new(Mutex::new(HashMap::new())),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            modified: Arc::new(Condvar::new()),
+        }
+    }
+
+    fn resolve<T: AsRef<Path>>(&self, partial: T) -> Result<Resolved, libc::c_int> {
+        let partial = partial.as_ref();
+        if let Some(overlay) = &self.overlay {
+            // The root directory always comes from the lower layer
+            if partial.parent().is_some() && overlay.contains(partial) {
+                debug!("{:?} is in the upper directory", partial);
+                return Ok(Resolved {
+                    access_type: AccessType::PassThrough,
+                    source: partial.to_owned(),
+                    real: overlay.real(partial),
+                    upper: true,
+                });
+            }
+            if overlay.hides(partial) {
+                debug!("{:?} is deleted in the upper directory", partial);
+                return Err(libc::ENOENT);
+            }
+        }
+        self.resolve_lower(partial)
+    }
+
+    /// Resolves `partial` in the projection of the source directory, ignoring the upper directory
+    fn resolve_lower(&self, partial: &Path) -> Result<Resolved, libc::c_int> {
+        if let Some(namespace) = &self.namespace {
+            return self.resolve_rewritten(namespace, partial);
+        }
+        match self.pm.source(&partial.as_os_str().to_os_string()) {
+            Some(source) => {
+                debug!("{:?} is a projected file", partial);
+                self.resolve_projected(PathBuf::from(source))
+            }
+            None => {
+                if let Some(resolved) = self.resolve_sidecar(partial) {
+                    debug!("{:?} is a sidecar file of {:?}", partial, resolved.source);
+                    return Ok(resolved);
+                }
+                let real = self.source_path(partial);
+                match self.source_attr(real.clone()) {
+                    Ok(stat) => {
+                        if !self.is_visible(partial, stat.kind, &AccessType::PassThrough) {
+                            // The name may be taken by a projected file (see `CollisionPolicy`)
+                            if let Some(source) = self.find_projection_source(partial) {
+                                debug!("{:?} is a projected file of {:?}", partial, source);
+                                return self.resolve_projected(source);
+                            }
+                            debug!("{:?} is hidden by the projection configuration", partial);
+                            return Err(libc::ENOENT);
+                        }
+                    }
+                    Err(_) => {
+                        if let Some(source) = self.find_projection_source(partial) {
+                            debug!("{:?} is a projected file of {:?}", partial, source);
+                            return self.resolve_projected(source);
+                        }
+                    }
+                }
+                debug!("{:?} is a non-projected file", partial);
+                Ok(Resolved {
+                    access_type: AccessType::PassThrough,
+                    source: partial.to_owned(),
+                    real: real,
+                    upper: false,
+                })
+            }
+        }
+    }
+
+    /// Makes the real path of the projected or sidecar file `resolved` the one of its content, when the source file isn't hashed yet
+    /// (see `CacheLayout::Content`): resolving doesn't hash it, which would read the whole file on every lookup.
+    fn hashed(&self, resolved: Resolved) -> Resolved {
+        if self.pm.layout != CacheLayout::Content
+            || resolved.upper
+            || resolved.access_type == AccessType::PassThrough
+        {
+            return resolved;
+        }
+        match self
+            .pm
+            .cache_file(&resolved.source, &resolved.access_type, self, true)
+        {
+            Some(real) => Resolved {
+                real: real,
+                ..resolved
+            },
+            None => resolved,
+        }
+    }
+
+    /// Resolves the projected file of `source`, which is the rewritten symbolic link itself if `source` is one (see `SymlinkMode::Rewrite`).
+    /// Fails if it has no name in the mount (anymore), because of a name collision.
+    fn resolve_projected(&self, source: PathBuf) -> Result<Resolved, libc::c_int> {
+        let real = self.source_path(&source);
+        match self.source_attr(real.clone()) {
+            Ok(stat) if stat.kind == FileType::Symlink => Ok(Resolved {
+                access_type: AccessType::PassThrough,
+                source: source,
+                real: real,
+                upper: false,
+            }),
+            _ => Ok(Resolved {
+                access_type: AccessType::Projected,
+                real: self
+                    .pm
+                    .cache_file(&source, &AccessType::Projected, self, false)
+                    .ok_or(libc::ENOENT)?,
+                source: source,
+                upper: false,
+            }),
+        }
+    }
+
+    /// Looks for the (visible) source file projected to `partial`, by listing the source directory it would be in.
+    /// This recognises projected files accessed directly (e.g. from a playlist) before their directory is listed.
+    fn find_projection_source(&self, partial: &Path) -> Option<PathBuf> {
+        let dir_partial = partial.parent()?;
+        let entries = fs::read_dir(self.source_path(dir_partial)).ok()?;
+        for entry in entries.filter_map(Result::ok) {
+            let source = dir_partial.join(entry.file_name());
+            if self.pm.projected_name(&source, self).as_deref() != Some(partial) {
+                continue;
+            }
+            let kind = match self.source_attr(entry.path().into_os_string()) {
+                Ok(stat) => stat.kind,
+                Err(_) => continue,
+            };
+            let is_projected = match kind {
+                FileType::RegularFile => {
+                    self.pm.access_type(entry.path()) == AccessType::Projected
+                        && self.is_visible(&source, kind, &AccessType::Projected)
+                }
+                FileType::Symlink => self.projected_link_target(&source).is_some(),
+                _ => false,
+            };
+            if is_projected {
+                self.pm.map_name(&source, self);
+                return Some(source);
+            }
+        }
+        None
+    }
+
+    /// Recognises `partial` as a sidecar file, i.e. the name of a visible source file followed by the suffix of an applicable sidecar
+    fn resolve_sidecar(&self, partial: &Path) -> Option<Resolved> {
+        let name = partial.file_name()?.to_str()?;
+        for (index, sidecar) in self.pm.spec.sidecars().iter().enumerate() {
+            if !name.ends_with(&sidecar.suffix) || name.len() == sidecar.suffix.len() {
+                continue;
+            }
+            let source = partial.with_file_name(&name[..name.len() - sidecar.suffix.len()]);
+            if !sidecar.applies_to(&source) || self.pm.sidecar_hidden(partial, self) {
+                continue;
+            }
+            match self.source_attr(self.source_path(&source)) {
+                Ok(stat) if stat.kind == FileType::RegularFile => {
+                    let access_type = self.pm.access_type(self.source_path(&source));
+                    if self.is_visible(&source, stat.kind, &access_type) {
+                        return Some(Resolved {
+                            access_type: AccessType::Sidecar(index),
+                            real: self.pm.cache_file(
+                                &source,
+                                &AccessType::Sidecar(index),
+                                self,
+                                false,
+                            )?,
+                            source: source,
+                            upper: false,
+                        });
+                    }
+                }
+                _ => (),
+            }
+        }
+        None
+    }
+
+    fn resolve_rewritten(
+        &self,
+        namespace: &Namespace,
+        partial: &Path,
+    ) -> Result<Resolved, libc::c_int> {
+        match namespace.lookup(partial) {
+            Some(Node::File {
+                source,
+                access_type: AccessType::Projected,
+                ..
+            }) => match self.pm.destination(&source.as_os_str().to_os_string()) {
+                Some(_) => {
+                    debug!("{:?} is a projected file of {:?}", partial, source);
+                    Ok(Resolved {
+                        access_type: AccessType::Projected,
+                        real: self
+                            .pm
+                            .cache_file(&source, &AccessType::Projected, self, false)
+                            .ok_or(libc::ENOENT)?,
+                        source: source,
+                        upper: false,
+                    })
+                }
+                None => {
+                    error!("{:?} is not mapped to any projection", source);
+                    Err(libc::ENOENT)
+                }
+            },
+            Some(Node::File {
+                source,
+                access_type: AccessType::Sidecar(index),
+                ..
+            }) => Ok(Resolved {
+                access_type: AccessType::Sidecar(index),
+                real: self
+                    .pm
+                    .cache_file(&source, &AccessType::Sidecar(index), self, false)
+                    .ok_or(libc::ENOENT)?,
+                source: source,
+                upper: false,
+            }),
+            Some(Node::File { source, .. }) => Ok(Resolved {
+                access_type: AccessType::PassThrough,
+                real: self.source_path(&source),
+                source: source,
+                upper: false,
+            }),
+            Some(Node::Directory { source }) => {
+                // Directories only existing in the mount take the attributes of the source directory
+                let source = source.unwrap_or_else(|| PathBuf::from("/"));
+                Ok(Resolved {
+                    access_type: AccessType::PassThrough,
+                    real: self.source_path(&source),
+                    source: source,
+                    upper: false,
+                })
+            }
+            None => Err(libc::ENOENT),
+        }
+    }
+
+    /// Computes the path in the mount of an entry, when the hierarchy is rewritten.
+    /// parameter `partial` is the relative partial path the entry would appear at without rewriting;
+    /// `source` is the relative partial path of the source file, for files which may be laid out by their metadata
+    fn virtual_path(&self, partial: &Path, source: Option<&Path>) -> PathBuf {
+        let mut relative = partial.strip_prefix("/").unwrap_or(partial).to_owned();
+        if let Some(source) = source {
+            if let Some(laid_out) = self
+                .pm
+                .spec
+                .layout_path(&self.source_path(source), &relative)
+            {
+                relative = laid_out;
+            }
+        }
+        Path::new("/").join(self.pm.spec.rewrite_path(&relative))
+    }
+
+    /// Adds the visible entries under the source directory `dir_partial` (recursively) to `namespace`.
+    /// Returns whether anything has been added.
+    fn populate(&self, namespace: &Namespace, dir_partial: &Path) -> bool {
+        let entries = match fs::read_dir(self.source_path(dir_partial)) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("can't list {:?}: {}", dir_partial, e);
+                return false;
+            }
+        };
+        let mut populated = false;
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+            let partial = PathBuf::from(dir_partial).join(entry.file_name());
+            let kind = match self.source_attr(entry.path().into_os_string()) {
+                Ok(stat) => stat.kind,
+                Err(_) => continue,
+            };
+            populated |= self.populate_entry(namespace, partial, kind);
+        }
+        populated
+    }
+
+    /// Adds the entry `partial` of the source directory (recursively if it's a directory) to `namespace`, if it's visible.
+    /// Returns whether anything has been added.
+    fn populate_entry(&self, namespace: &Namespace, partial: PathBuf, kind: FileType) -> bool {
+        let mut populated = false;
+        if kind == FileType::Directory {
+            if self.pm.spec.is_excluded(partial.strip_prefix("/").unwrap()) {
+                return false;
+            }
+            let virtual_path = self.virtual_path(&partial, None);
+            if self.populate(namespace, &partial) {
+                namespace.attach(&virtual_path, partial);
+                populated = true;
+            } else if !self.pm.spec.prune_empty_dirs() {
+                let node = Node::Directory {
+                    source: Some(partial),
+                };
+                populated |= namespace.insert(virtual_path, node);
+            }
+            return populated;
+        }
+        let access_type = if kind == FileType::RegularFile {
+            self.pm.access_type(self.source_path(&partial))
+        } else {
+            AccessType::PassThrough
+        };
+        if !self.is_visible(&partial, kind, &access_type) {
+            return false;
+        }
+        if kind == FileType::Symlink && self.projected_link_target(&partial).is_some() {
+            let link_partial = self.rewritten_link(&partial);
+            let node = Node::File {
+                source: partial.clone(),
+                kind: kind,
+                access_type: AccessType::PassThrough,
+            };
+            return namespace.insert(self.virtual_path(&link_partial, Some(&partial)), node);
+        }
+        if access_type == AccessType::Projected {
+            if let Some(dest_partial) = self.pm.map_name(&partial, self) {
+                let node = Node::File {
+                    source: partial.clone(),
+                    kind: kind,
+                    access_type: AccessType::Projected,
+                };
+                populated |=
+                    namespace.insert(self.virtual_path(&dest_partial, Some(&partial)), node);
+            }
+        }
+        if access_type == AccessType::PassThrough || self.pm.spec.show_original() {
+            let node = Node::File {
+                source: partial.clone(),
+                kind: kind,
+                access_type: AccessType::PassThrough,
+            };
+            populated |= namespace.insert(self.virtual_path(&partial, Some(&partial)), node);
+        }
+        if kind == FileType::RegularFile {
+            for (index, sidecar) in self.pm.spec.sidecars().iter().enumerate() {
+                if sidecar.applies_to(&partial) {
+                    let virtual_path = fsop::append_suffix(
+                        self.virtual_path(&partial, Some(&partial)),
+                        &sidecar.suffix,
+                    );
+                    let node = Node::File {
+                        source: partial.clone(),
+                        kind: kind,
+                        access_type: AccessType::Sidecar(index),
+                    };
+                    populated |= namespace.insert(virtual_path, node);
+                }
+            }
+        }
+        populated
+    }
+
+    /// Test if an entry of the source directory appears in the mount, when accessed as `access_type`.
+    /// parameter `partial` is the relative partial path of the entry in the source directory
+    fn is_visible(&self, partial: &Path, filetype: FileType, access_type: &AccessType) -> bool {
+        let relative = partial.strip_prefix("/").unwrap_or(partial);
+        if relative.as_os_str().is_empty() {
+            return true;
+        }
+        if self.pm.spec.is_excluded(relative) {
+            return false;
+        }
+        if fsop::temp_target(partial).is_some() {
+            // Being written by a reverse conversion
+            return false;
+        }
+        match (access_type, filetype) {
+            (AccessType::Projected, _) | (AccessType::Sidecar(_), _) => true,
+            (AccessType::PassThrough, FileType::Directory) => {
+                !self.pm.spec.prune_empty_dirs() || self.has_visible_content_cached(partial)
+            }
+            (AccessType::PassThrough, FileType::RegularFile)
+                if self.pm.spec.show_original()
+                    && self.pm.access_type(self.source_path(partial)) == AccessType::Projected =>
+            {
+                true
+            }
+            (AccessType::PassThrough, FileType::Symlink)
+                if self.projected_link_target(partial).is_some() =>
+            {
+                true
+            }
+            (AccessType::PassThrough, FileType::RegularFile)
+                if self.pm.mount_name(partial, self) == Some(MountName::Hidden) =>
+            {
+                false
+            }
+            (AccessType::PassThrough, _) => self.pm.spec.passthrough() == PassThroughMode::Show,
+        }
+    }
+
+    /// Same as `has_visible_content()`, remembering the result until the directory or one of the subdirectories it depends on changes.
+    /// A change deeper in the hierarchy only changes the modification time of the directory it's in,
+    /// so the modification times of those subdirectories are checked again as well.
+    fn has_visible_content_cached(&self, dir_partial: &Path) -> bool {
+        if let Some(visible) = self.cached_visibility(dir_partial) {
+            return visible;
+        }
+        let mtime = match fsop::getattr(self.source_path(dir_partial)) {
+            Ok(stat) => stat.mtime,
+            Err(_) => return self.has_visible_content(dir_partial).0,
+        };
+        // Not holding the lock, as the subdirectories are checked (and cached) as well
+        let (visible, subdirs) = self.has_visible_content(dir_partial);
+        self.visible_dirs.lock().unwrap().insert(
+            dir_partial.to_owned(),
+            Visibility {
+                mtime: mtime,
+                visible: visible,
+                subdirs: subdirs,
+            },
+        );
+        visible
+    }
+
+    /// Whether the source directory `dir_partial` has visible content, if it's remembered and still valid
+    fn cached_visibility(&self, dir_partial: &Path) -> Option<bool> {
+        let checked = self
+            .visible_dirs
+            .lock()
+            .unwrap()
+            .get(dir_partial)
+            .cloned()?;
+        if fsop::getattr(self.source_path(dir_partial)).ok()?.mtime != checked.mtime {
+            return None;
+        }
+        for subdir in &checked.subdirs {
+            self.cached_visibility(subdir)?;
+        }
+        Some(checked.visible)
+    }
+
+    /// Forgets whether the directories containing the source entry `partial`, and the ones under it, have visible content
+    fn forget_visibility(&self, partial: &Path) {
+        self.visible_dirs
+            .lock()
+            .unwrap()
+            .retain(|dir, _| !partial.starts_with(dir) && !dir.starts_with(partial));
+    }
+
+    /// Test if a source directory contains anything (recursively) which appears in the mount.
+    /// Also returns the subdirectories checked (see `has_visible_content_cached()`).
+    fn has_visible_content(&self, dir_partial: &Path) -> (bool, Vec<PathBuf>) {
+        let mut subdirs = Vec::new();
+        let entries = match fs::read_dir(self.source_path(dir_partial)) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("can't list {:?} for pruning: {}", dir_partial, e);
+                return (true, subdirs);
+            }
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+            let partial = PathBuf::from(dir_partial).join(entry.file_name());
+            let filetype = match self.source_attr(entry.path().into_os_string()) {
+                Ok(stat) => stat.kind,
+                Err(_) => continue,
+            };
+            let access_type = if filetype == FileType::RegularFile {
+                self.pm.access_type(self.source_path(&partial))
+            } else {
+                AccessType::PassThrough
+            };
+            let visible = self.is_visible(&partial, filetype, &access_type);
+            // Excluded directories are not checked, so they don't change anything
+            if filetype == FileType::Directory
+                && !self
+                    .pm
+                    .spec
+                    .is_excluded(partial.strip_prefix("/").unwrap_or(&partial))
+            {
+                subdirs.push(partial);
+            }
+            if visible {
+                return (true, subdirs);
+            }
+        }
+        (false, subdirs)
+    }
+
+    /// Lists the directory `path` from the source directory, through `fh` which is opened by `opendir()`
+    fn readdir_source(&self, path: &Path, fh: u64) -> ResultReaddir {
+        let mut entries: Vec<DirectoryEntry> = vec![];
+
+        if fh == 0 {
+            error!("readdir: missing fh");
+            return Err(libc::EINVAL);
+        }
+
+        loop {
+            match libc_wrappers::readdir(fh) {
+                Ok(Some(entry)) => {
+                    let name_c = unsafe { CStr::from_ptr(entry.d_name.as_ptr()) };
+                    let name = OsStr::from_bytes(name_c.to_bytes()).to_owned();
+
+                    let filetype = match entry.d_type {
+                        libc::DT_DIR => FileType::Directory,
+                        libc::DT_REG => FileType::RegularFile,
+                        libc::DT_LNK => FileType::Symlink,
+                        libc::DT_BLK => FileType::BlockDevice,
+                        libc::DT_CHR => FileType::CharDevice,
+                        libc::DT_FIFO => FileType::NamedPipe,
+                        libc::DT_SOCK => {
+                            warn!("FUSE doesn't support Socket file type; translating to NamedPipe instead.");
+                            FileType::NamedPipe
+                        }
+                        0 | _ => {
+                            let entry_path = PathBuf::from(path).join(&name);
+                            let source_path = self.source_path(&entry_path);
+                            match libc_wrappers::lstat(source_path) {
+                                Ok(stat64) => br::mode_to_filetype(stat64.st_mode),
+                                Err(errno) => {
+                                    let ioerr = io::Error::from_raw_os_error(errno);
+                                    panic!("lstat failed after readdir_r gave no file type for {:?}: {}",
+                                           entry_path, ioerr);
+                                }
+                            }
+                        }
+                    };
+
+                    info!("readdir() :: filename: {:?}", &name);
+                    let entry_path = PathBuf::from(path).join(&name);
+                    let filetype = if filetype == FileType::Symlink {
+                        match self.source_attr(self.source_path(&entry_path)) {
+                            Ok(stat) => stat.kind,
+                            Err(_) => filetype,
+                        }
+                    } else {
+                        filetype
+                    };
+                    if name == "." || name == ".." {
+                        entries.push(DirectoryEntry {
+                            name,
+                            kind: filetype,
+                        })
+                    } else if filetype == FileType::RegularFile {
+                        let access_type = self.pm.access_type(self.source_path(&entry_path));
+                        if !self.is_visible(&entry_path, filetype, &access_type) {
+                            debug!("readdir() :: {:?} is hidden", &entry_path);
+                            continue;
+                        }
+                        let is_projected = access_type == AccessType::Projected;
+                        let result_name = match self.sniff_projection(path, &name, access_type) {
+                            Some(result_path) => {
+                                Path::new(&result_path).file_name().unwrap().to_owned()
+                            }
+                            None => {
+                                debug!(
+                                    "readdir() :: {:?} is hidden by a name collision",
+                                    &entry_path
+                                );
+                                continue;
+                            }
+                        };
+                        if is_projected {
+                            // Only once the name collisions are resolved, as the loser isn't generated
+                            self.queue(&entry_path, &AccessType::Projected);
+                        }
+                        if is_projected && self.pm.spec.show_original() && result_name != name {
+                            entries.push(DirectoryEntry {
+                                name: name.clone(),
+                                kind: filetype,
+                            })
+                        }
+                        entries.push(DirectoryEntry {
+                            name: result_name,
+                            kind: filetype,
+                        });
+                        for (index, sidecar) in self.pm.spec.sidecars().iter().enumerate() {
+                            let sidecar_name =
+                                fsop::append_suffix(Path::new(&name), &sidecar.suffix);
+                            if sidecar.applies_to(&entry_path)
+                                && !self
+                                    .pm
+                                    .sidecar_hidden(&entry_path.with_file_name(&sidecar_name), self)
+                            {
+                                self.queue(&entry_path, &AccessType::Sidecar(index));
+                                entries.push(DirectoryEntry {
+                                    name: sidecar_name.into_os_string(),
+                                    kind: filetype,
+                                });
+                            }
+                        }
+                    } else if filetype == FileType::Symlink
+                        && self.projected_link_target(&entry_path).is_some()
+                    {
+                        if self.is_visible(&entry_path, filetype, &AccessType::PassThrough) {
+                            let link_partial = self.rewritten_link(&entry_path);
+                            entries.push(DirectoryEntry {
+                                name: link_partial.file_name().unwrap().to_owned(),
+                                kind: filetype,
+                            })
+                        }
+                    } else if self.is_visible(&entry_path, filetype, &AccessType::PassThrough) {
+                        entries.push(DirectoryEntry {
+                            name,
+                            kind: filetype,
+                        })
+                    } else {
+                        debug!("readdir() :: {:?} is hidden", &entry_path);
+                    }
+                }
+                Ok(None) => {
+                    break;
+                }
+                Err(e) => {
+                    error!("readdir: {:?}: {}", path, e);
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn readdir_rewritten(&self, namespace: &Namespace, path: &Path) -> ResultReaddir {
+        let children = match namespace.children(path) {
+            Some(children) => children,
+            None => return Err(libc::ENOENT),
+        };
+        let mut entries = dot_entries();
+        for (name, node) in children {
+            info!("readdir() :: filename: {:?}", &name);
+            let kind = match node {
+                Node::Directory { .. } => FileType::Directory,
+                Node::File {
+                    source,
+                    kind,
+                    access_type,
+                } => {
+                    if access_type != AccessType::PassThrough {
+                        self.queue(&source, &access_type);
+                    }
+                    kind
+                }
+            };
+            entries.push(DirectoryEntry { name, kind });
+        }
+        Ok(entries)
+    }
+
+    fn sniff_projection(
+        &self,
+        dir_path: &Path,
+        filename: &OsStr,
+        access_type: AccessType,
+    ) -> Option<OsString> {
+        let partial = &PathBuf::from(dir_path).join(filename);
+        match access_type {
+            AccessType::PassThrough | AccessType::Sidecar(_) => Some(self.source_path(partial)),
+            // Only the name is computed here; the projection happens when the file is opened
+            AccessType::Projected => self
+                .pm
+                .map_name(partial, self)
+                .map(|dest_partial| self.cache_path(dest_partial)),
+        }
+    }
+
+    /// Attributes of a projected or sidecar file which is not (completely) generated yet:
+    /// those of the source file, with an estimated size and no allocated blocks.
+    fn placeholder_attr(&self, path: &Path, resolved: &Resolved) -> ResultEntry {
+        match self.source_attr(self.source_path(&resolved.source)) {
+            Ok(mut stat) => {
+                stat.size = self.pm.spec.placeholder_size(stat.size);
+                stat.blocks = 0;
+                Ok((PLACEHOLDER_TTL, stat))
+            }
+            Err(e) => {
+                let err = io::Error::from_raw_os_error(e);
+                error!("lstat({:?}): {}", path, err);
+                Err(err.raw_os_error().unwrap())
+            }
+        }
+    }
+
+    /// Queues the generation of the projected or sidecar file of `source` in the background, if enabled
+    fn queue(&self, source: &Path, access_type: &AccessType) {
+        let workers = match &self.workers {
+            Some(workers) => workers,
+            None => return,
+        };
+        let job = (source.to_owned(), access_type.clone());
+        if !self.pm.queued.lock().unwrap().insert(job.clone()) {
+            return;
+        }
+        let pm = Arc::clone(&self.pm);
+        let dirs = Directories {
+            source_dir: self.source_dir.clone(),
+            cache_dir: self.cache_dir.clone(),
+        };
+        workers.submit(move || {
+            pm.queued.lock().unwrap().remove(&job);
+            let (source, access_type) = job;
+            debug!("background generation of {:?} ({:?})", source, access_type);
+            // A failure is already logged, and retried when the file is opened
+            let _ = pm.prefetch(&source, &access_type, &dirs);
+        });
+    }
+
+    /// Applies the changes of the source directory reported by the watcher as they come, in a dedicated thread.
+    /// The kernel's caches can't be invalidated through this version of FUSE, so the changes may take up to `TTL` to appear.
+    fn start_applying_changes(&self, changes: Receiver<Change>) {
+        let fs = self.clone();
+        let spawned = thread::Builder::new()
+            .name("projfs-changes".to_string())
+            .spawn(move || {
+                for change in changes {
+                    debug!("source change: {:?}", change);
+                    fs.apply_change(change);
+                }
+            });
+        if let Err(e) = spawned {
+            error!("thread applying the source changes can't be spawned: {}", e);
+        }
+    }
+
+    /// Applies a change of the source directory, reported by the watcher or made through the mount
+    fn apply_change(&self, change: Change) {
+        if self.pm.spec.prune_empty_dirs() {
+            match &change {
+                Change::Created { path, .. }
+                | Change::Modified(path)
+                | Change::Removed { path, .. } => self.forget_visibility(path),
+                Change::Renamed { from, to, .. } => {
+                    self.forget_visibility(from);
+                    self.forget_visibility(to);
+                }
+            }
+        }
+        match change {
+            Change::Created { path, .. } => self.add_source(&path),
+            Change::Modified(path) => {
+                if let Some(namespace) = &self.namespace {
+                    // The metadata may have changed, and so the place of the file in the layout
+                    namespace.remove_source(&path);
+                }
+                self.add_source(&path);
+            }
+            // The cache files are moved before the names of `from` are forgotten
+            Change::Removed { path, is_dir } => {
+                self.move_cache(&path, None, is_dir);
+                self.forget_source(&path);
+            }
+            Change::Renamed { from, to, is_dir } => {
+                self.move_cache(&from, Some(&to), is_dir);
+                self.forget_source(&from);
+                self.add_source(&to);
+            }
+        }
+    }
+
+    /// Takes the new or modified entry `partial` of the source directory into account, and queues the generation of its files
+    fn add_source(&self, partial: &Path) {
+        let kind = match self.source_attr(self.source_path(partial)) {
+            Ok(stat) => stat.kind,
+            // Already gone, which is reported by a later change
+            Err(_) => return,
+        };
+        if let Some(namespace) = &self.namespace {
+            self.populate_entry(namespace, partial.to_owned(), kind);
+        }
+        if kind != FileType::RegularFile {
+            return;
+        }
+        let access_type = self.pm.access_type(self.source_path(partial));
+        if !self.is_visible(partial, kind, &access_type) {
+            return;
+        }
+        if access_type == AccessType::Projected && self.pm.map_name(partial, self).is_some() {
+            self.queue(partial, &access_type);
+        }
+        for (index, sidecar) in self.pm.spec.sidecars().iter().enumerate() {
+            if sidecar.applies_to(partial) {
+                self.queue(partial, &AccessType::Sidecar(index));
+            }
+        }
+    }
+
+    /// Forgets the entry `partial` of the source directory and everything under it
+    fn forget_source(&self, partial: &Path) {
+        self.pm.forget(partial);
+        if let Some(namespace) = &self.namespace {
+            namespace.remove_source(partial);
+        }
+    }
+
+    /// Moves the files generated from the source entry `from` to where the ones of `to` go, or removes them if `to` is `None`.
+    /// The files which don't apply to `to` (e.g. because it's not projected anymore) are removed as well.
+    /// With the content cache layout, the files don't depend on the source path and are left to the garbage collection.
+    fn move_cache(&self, from: &Path, to: Option<&Path>, is_dir: bool) {
+        if self.pm.layout == CacheLayout::Content {
+            return;
+        }
+        let mut moves = Vec::new();
+        if is_dir {
+            moves.push((self.cache_path(from), to.map(|to| self.cache_path(to))));
+        } else {
+            let projected = |partial: &Path| {
+                self.pm.access_type(self.source_path(partial)) == AccessType::Projected
+            };
+            // The names of the projected files depend on the name collisions, so they're those in use
+            if let Some(old) = self.pm.destination(&from.as_os_str().to_os_string()) {
+                let new = to
+                    .filter(|to| projected(to))
+                    .and_then(|to| self.pm.projected_name(to, self));
+                moves.push((self.cache_path(old), new.map(|new| self.cache_path(new))));
+            }
+            for sidecar in self.pm.spec.sidecars() {
+                if sidecar.applies_to(from) {
+                    let dest = |partial: &Path| {
+                        self.cache_path(fsop::append_suffix(partial, &sidecar.suffix))
+                    };
+                    moves.push((dest(from), to.filter(|to| sidecar.applies_to(to)).map(dest)));
+                }
+            }
+        }
+        for (old, new) in moves {
+            debug!("cache of {:?}: {:?} -> {:?}", from, old, new);
+            if let Err(e) = fsop::relocate(old.clone(), new) {
+                error!("can't move or remove cached {:?}: {}", old, e);
+            }
+        }
+    }
+
+    /// Saves the index in the background every `index::SAVE_INTERVAL`, when it has changed
+    fn start_saving_index(&self) {
+        let pm = Arc::clone(&self.pm);
+        let spawned = thread::Builder::new()
+            .name("projfs-index".to_string())
+            .spawn(move || loop {
+                thread::sleep(index::SAVE_INTERVAL);
+                pm.index.save();
+            });
+        if let Err(e) = spawned {
+            error!("index saving thread can't be spawned: {}", e);
+        }
+    }
+
+    /// Lists every directory under `path` (recursively), which queues all the projected and sidecar files
+    fn prefetch(&self, path: &Path) {
+        let entries = match &self.namespace {
+            Some(namespace) => self.readdir_rewritten(namespace, path),
+            None => match libc_wrappers::opendir(self.source_path(path)) {
+                Ok(fh) => {
+                    let entries = self.readdir_source(path, fh);
+                    if let Err(e) = libc_wrappers::closedir(fh) {
+                        error!("closedir({:?}): {}", path, io::Error::from_raw_os_error(e));
+                    }
+                    entries
+                }
+                Err(e) => Err(e),
+            },
+        };
+        match entries {
+            Ok(entries) => {
+                for entry in entries {
+                    if entry.kind == FileType::Directory && entry.name != "." && entry.name != ".."
+                    {
+                        self.prefetch(&path.join(&entry.name));
+                    }
+                }
+            }
+            Err(e) => warn!("prefetch({:?}): {}", path, io::Error::from_raw_os_error(e)),
+        }
+    }
+
+    /// The attributes of the entry `real` of the source directory.
+    /// With `SymlinkMode::Follow`, those of the file a symbolic link leads to, if it's a regular file.
+    fn source_attr(&self, real: OsString) -> Result<FileAttr, libc::c_int> {
+        let stat = fsop::getattr(real.clone())?;
+        if stat.kind == FileType::Symlink && self.options.symlinks == SymlinkMode::Follow {
+            match fsop::stat(real) {
+                Ok(target) if target.kind == FileType::RegularFile => return Ok(target),
+                _ => (),
+            }
+        }
+        Ok(stat)
+    }
+
+    /// The file the symbolic link `partial` of the source directory leads to (as a relative partial path),
+    /// if links are rewritten (see `SymlinkMode::Rewrite`) and it's a visible projected file
+    fn projected_link_target(&self, partial: &Path) -> Option<PathBuf> {
+        if self.options.symlinks != SymlinkMode::Rewrite {
+            return None;
+        }
+        let target = fs::read_link(self.source_path(partial)).ok()?;
+        if target.is_absolute() {
+            return None;
+        }
+        let target_partial = fsop::normalize(&partial.parent()?.join(target))?;
+        let kind = fsop::getattr(self.source_path(&target_partial)).ok()?.kind;
+        if kind == FileType::RegularFile
+            && self.pm.access_type(self.source_path(&target_partial)) == AccessType::Projected
+            && self.is_visible(&target_partial, kind, &AccessType::Projected)
+        {
+            Some(target_partial)
+        } else {
+            None
+        }
+    }
+
+    /// The relative partial path of the rewritten symbolic link `partial`, renamed like a projected file if its name is of a projected type
+    fn rewritten_link(&self, partial: &Path) -> PathBuf {
+        if self.pm.access_type(self.source_path(partial)) == AccessType::Projected {
+            if let Some(link_partial) = self.pm.map_name(partial, self) {
+                return link_partial;
+            }
+        }
+        partial.to_owned()
+    }
+
+    /// The real path of the entry whose attributes (besides the size) are the ones of the entry `resolved` of the mount
+    fn attributes_path(&self, resolved: &Resolved) -> OsString {
+        if resolved.upper {
+            resolved.real.clone()
+        } else {
+            // Projected and sidecar files have the attributes of their source file
+            self.source_path(&resolved.source)
+        }
+    }
+
+    fn source_path<T: AsRef<Path>>(&self, partial: T) -> OsString {
+        self.source(partial.as_ref())
+    }
+
+    fn cache_path<T: AsRef<Path>>(&self, partial: T) -> OsString {
+        self.cache(partial.as_ref())
+    }
+}
+
+impl ProjectionResolver for ProjectionFS {
+    fn source(&self, partial: &Path) -> OsString {
+        fsop::real_path(&self.source_dir, partial)
+    }
+
+    fn cache(&self, partial: &Path) -> OsString {
+        fsop::real_path(&self.cache_dir, partial)
+    }
+}
+
+impl FilesystemMT for ProjectionFS {
+    fn init(&self, _req: RequestInfo) -> ResultEmpty {
+        debug!("init");
+        // Before anything can start generating files (the workers only get jobs from the watcher and the mount)
+        self.pm.remove_temp_files(self, Path::new("/"));
+        if self.options.watch {
+            // Watching starts first, so nothing changed while building the hierarchy is missed
+            match watcher::watch(self.source_dir.clone()) {
+                Ok(changes) => self.start_applying_changes(changes),
+                Err(e) => error!("can't watch {:?}: {}", self.source_dir, e),
+            }
+        }
+        if let Some(namespace) = &self.namespace {
+            info!("building the rewritten hierarchy of {:?}", self.source_dir);
+            self.populate(namespace, Path::new("/"));
+        }
+        self.start_saving_index();
+        self.pm.scan_usage(self);
+        if let Some(interval) = self.options.gc_interval {
+            self.start_gc(interval);
+        }
+        if self.workers.is_some() && self.options.prefetch == Prefetch::Mount {
+            info!("queueing all files for background projection");
+            self.prefetch(Path::new("/"));
+        }
+        Ok(())
+    }
+
+    fn destroy(&self, _req: RequestInfo) {
+        debug!("destroy");
+        self.pm.index.save();
+    }
+
+    fn getattr(&self, _req: RequestInfo, path: &Path, fh: Option<u64>) -> ResultEntry {
+        debug!(
+            "getattr: {:?} ({} filehandle)",
+            path,
+            if let Some(_) = fh { "with" } else { "without" }
+        );
+
+        // A file whose opening is deferred has the attributes of its path
+        if let Some(fh) = fh.and_then(|fh| self.opened_fh(fh)) {
+            // Only used in setattr. Never used for read-only filesystem
+            match libc_wrappers::fstat(fh) {
+                Ok(stat) => Ok((TTL, br::stat_to_fuse(stat))),
+                Err(e) => Err(e),
+            }
+        } else {
+            let resolved = self.resolve(path)?;
+
+            if let Some(size) = self
+                .pending
+                .lock()
+                .unwrap()
+                .get(path)
+                .map(|pending| pending.size)
+            {
+                debug!("{:?} is modified once generated", path);
+                let mut stat = self.source_attr(self.attributes_path(&resolved))?;
+                stat.size = size;
+                stat.blocks = 0;
+                return Ok((PLACEHOLDER_TTL, stat));
+            }
+            if resolved.access_type != AccessType::PassThrough {
+                let status = self.pm.status(
+                    &self.source_path(&resolved.source),
+                    &resolved.real,
+                    &resolved.access_type,
+                );
+                if status != ProjectionStatus::Done {
+                    debug!("{:?} is {:?}; using placeholder attributes", path, status);
+                    return self.placeholder_attr(path, &resolved);
+                }
+            }
+
+            match self.source_attr(resolved.real.clone()) {
+                Ok(stat) => match resolved.access_type {
+                    AccessType::PassThrough => Ok((TTL, stat)),
+                    AccessType::Projected | AccessType::Sidecar(_) => {
+                        match self.source_attr(self.source_path(&resolved.source)) {
+                            Ok(mut stat_real) => {
+                                stat_real.size = stat.size;
+                                stat_real.blocks = stat.blocks;
+                                Ok((TTL, stat_real))
+                            }
+                            Err(e) => {
+                                let err = io::Error::from_raw_os_error(e);
+                                error!("lstat({:?}): {}", path, err);
+                                Err(err.raw_os_error().unwrap())
+                            }
+                        }
+                    }
+                },
+                Err(e) => {
+                    let err = io::Error::from_raw_os_error(e);
+                    error!("lstat({:?}): {}", path, err);
+                    Err(err.raw_os_error().unwrap())
+                }
+            }
+        }
+    }
+
+    fn readlink(&self, _req: RequestInfo, path: &Path) -> ResultData {
+        debug!("readlink: {:?}", path);
+
+        let resolved = self.resolve(path)?;
+        let target = match fs::read_link(&resolved.real) {
+            Ok(target) => target,
+            Err(e) => {
+                error!("readlink({:?}): {}", path, e);
+                return Err(e.raw_os_error().unwrap());
+            }
+        };
+        if resolved.upper {
+            // Provided as-is
+            return Ok(target.into_os_string().into_vec());
+        }
+        let dest_partial =
+            self.projected_link_target(&resolved.source)
+                .and_then(|target_partial| {
+                    let dest_partial = self.pm.map_name(&target_partial, self)?;
+                    Some((target_partial, dest_partial))
+                });
+        let target = match dest_partial {
+            Some((target_partial, dest_partial)) => match &self.namespace {
+                // The link and its target may have moved differently
+                Some(_) => {
+                    let virtual_target = self.virtual_path(&dest_partial, Some(&target_partial));
+                    fsop::relative_path(path.parent().unwrap(), &virtual_target)
+                }
+                None => target.with_file_name(dest_partial.file_name().unwrap()),
+            },
+            None => target,
+        };
+        Ok(target.into_os_string().into_vec())
+    }
+
+    fn getxattr(&self, _req: RequestInfo, path: &Path, name: &OsStr, size: u32) -> ResultXattr {
+        debug!("getxattr: {:?} {:?} ({} bytes)", path, name, size);
+
+        let resolved = self.resolve(path)?;
+        let virtual_name = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(VIRTUAL_XATTR_PREFIX));
+        let value = match virtual_name {
+            Some(virtual_name) if resolved.access_type != AccessType::PassThrough => self
+                .virtual_xattr(&resolved, virtual_name)
+                .ok_or(libc::ENODATA)?,
+            // Projected and sidecar files have the attributes of their source file
+            _ => fsop::getxattr(self.attributes_path(&resolved), name)?,
+        };
+        xattr_reply(value, size)
+    }
+
+    fn listxattr(&self, _req: RequestInfo, path: &Path, size: u32) -> ResultXattr {
+        debug!("listxattr: {:?} ({} bytes)", path, size);
+
+        let resolved = self.resolve(path)?;
+        let mut names = match fsop::listxattr(self.attributes_path(&resolved)) {
+            Ok(names) => names,
+            Err(libc::ENOTSUP) if resolved.access_type != AccessType::PassThrough => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        if resolved.access_type != AccessType::PassThrough {
+            for virtual_name in VIRTUAL_XATTRS.iter() {
+                if self.virtual_xattr(&resolved, virtual_name).is_some() {
+                    names.extend_from_slice(VIRTUAL_XATTR_PREFIX.as_bytes());
+                    names.extend_from_slice(virtual_name.as_bytes());
+                    names.push(0);
+                }
+            }
+        }
+        xattr_reply(names, size)
+    }
+
+    //checked
+    fn opendir(&self, _req: RequestInfo, path: &Path, _flags: u32) -> ResultOpen {
+        if self.overlay.is_some() && self.resolve(path)?.upper {
+            // Listed from the upper directory, along with the directory of the lower layer if any
+            debug!("opendir: {:?} (upper directory)", path);
+            return match (&self.namespace, self.lower_dir(path)) {
+                (None, Some(real)) => libc_wrappers::opendir(real).map(|fh| (fh, 0)),
+                _ => Ok((0, 0)),
+            };
+        }
+        if let Some(namespace) = &self.namespace {
+            // The listing comes from the namespace, so no real directory is opened
+            debug!("opendir: {:?} (rewritten hierarchy)", path);
+            return match namespace.lookup(path) {
+                Some(Node::Directory { .. }) => Ok((0, 0)),
+                Some(_) => Err(libc::ENOTDIR),
+                None => Err(libc::ENOENT),
+            };
+        }
+        let real = self.resolve(path)?.real;
+        debug!("opendir: {:?} (flags = {:#o})", real, _flags);
+        match libc_wrappers::opendir(real) {
+            Ok(fh) => Ok((fh, 0)),
+            Err(e) => {
+                let ioerr = io::Error::from_raw_os_error(e);
+                error!("opendir({:?}): {}", path, ioerr);
+                Err(e)
+            }
+        }
+    }
+
+    //checked
+    fn releasedir(&self, _req: RequestInfo, path: &Path, fh: u64, _flags: u32) -> ResultEmpty {
+        debug!("releasedir: {:?}", path);
+        if fh == 0 {
+            return Ok(());
+        }
+        libc_wrappers::closedir(fh)
+    }
+
+    //checked
+    fn readdir(&self, _req: RequestInfo, path: &Path, fh: u64) -> ResultReaddir {
+        debug!("readdir: {:?}", path);
+
+        let entries = if let Some(namespace) = &self.namespace {
+            self.readdir_rewritten(namespace, path)
+        } else if fh == 0 && self.overlay.is_some() {
+            // Only in the upper directory
+            Ok(dot_entries())
+        } else {
+            self.readdir_source(path, fh)
+        };
+
+        match &self.overlay {
+            Some(overlay) => self.merge_upper(overlay, path, entries),
+            None => entries,
+        }
+    }
+
+    fn open(&self, _req: RequestInfo, path: &Path, flags: u32) -> ResultOpen {
+        debug!("open: {:?} flags={:#x}", path, flags);
+
+        let flags_c = flags as libc::c_int;
+        let writing = flags_c & libc::O_ACCMODE != libc::O_RDONLY || flags_c & libc::O_TRUNC != 0;
+
+        let resolved = if writing {
+            self.resolve_modified(path, Modification::Content)?
+        } else {
+            self.resolve(path)?
+        };
+        let pending = self.pending.lock().unwrap().contains_key(path);
+        if pending
+            || resolved.access_type != AccessType::PassThrough
+                && !resolved.upper
+                && self.pm.status(
+                    &self.source_path(&resolved.source),
+                    &resolved.real,
+                    &resolved.access_type,
+                ) != ProjectionStatus::Done
+        {
+            let fh = self.next_deferred_fh.fetch_add(1, Ordering::SeqCst);
+            debug!(
+                "{:?} isn't generated yet; opening it on first use as {}",
+                path, fh
+            );
+            let deferred = DeferredOpen::Pending(path.to_owned(), flags, resolved);
+            self.deferred
+                .lock()
+                .unwrap()
+                .insert(fh, Arc::new(Mutex::new(deferred)));
+            // Likely streamed (see `open_now()`)
+            return Ok((fh, FOPEN_DIRECT_IO));
+        }
+        self.open_now(path, flags, resolved)
+    }
+
+    fn release(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        fh: u64,
+        _flags: u32,
+        _lock_owner: u64,
+        _flush: bool,
+    ) -> ResultEmpty {
+        debug!("release: {:?}", path);
+        let fh = match self.deferred.lock().unwrap().remove(&fh) {
+            Some(deferred) => match *deferred.lock().unwrap() {
+                DeferredOpen::Opened(fh) => fh,
+                _ => return Ok(()),
+            },
+            None => fh,
+        };
+        let file = self.open_files.lock().unwrap().remove(&fh);
+        if let Some(file) = &file {
+            self.pm.closed(&file.real);
+        }
+        let closed = libc_wrappers::close(fh);
+        if let Some(partial) = self.written.lock().unwrap().remove(&fh) {
+            match file.filter(|file| file.modified) {
+                // A modified projected or sidecar file is converted back into its source file, if its rule can
+                Some(file) => self.reverse_later(partial, file.real, file.access_type),
+                None => self.apply_change_later(partial),
+            }
+        }
+        closed
+    }
+
+    fn read(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        fh: u64,
+        offset: u64,
+        size: u32,
+        result: impl FnOnce(Result<&[u8], libc::c_int>),
+    ) {
+        debug!("read: {:?} {:#x} @ {:#x}", path, size, offset);
+        let fh = match self.real_fh(fh) {
+            Ok(fh) => fh,
+            Err(e) => {
+                result(Err(e));
+                return;
+            }
+        };
+        let mut file = unsafe { UnmanagedFile::new(fh) };
+
+        let mut data = Vec::<u8>::with_capacity(size as usize);
+        unsafe { data.set_len(size as usize) };
+
+        if let Err(e) = file.seek(SeekFrom::Start(offset)) {
+            error!("seek({:?}, {}): {}", path, offset, e);
+            result(Err(e.raw_os_error().unwrap()));
+            return;
+        }
+        let mut stream = match self.open_files.lock().unwrap().get(&fh) {
+            Some(file) if file.streaming => Some(file.real.clone()),
+            _ => None,
+        };
+        let mut filled = 0;
+        loop {
+            match file.read(&mut data[filled..]) {
+                Ok(0) => {
+                    // Past the current end: wait for more data only if nothing can be returned yet
+                    match &stream {
+                        Some(dest) if filled == 0 => {
+                            if !self.pm.wait_for_data(dest) {
+                                // Complete (or failed) since the last read, which may have missed the last data written
+                                if self.pm.failures.lock().unwrap().contains_key(dest) {
+                                    error!("read {:?}: {:?} hasn't been generated", path, dest);
+                                    result(Err(libc::EIO));
+                                    return;
+                                }
+                                stream = None;
+                            }
+                        }
+                        _ => break,
+                    }
+                }
+                Ok(n) => {
+                    filled += n;
+                    if filled == data.len() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    error!("read {:?}, {:#x} @ {:#x}: {}", path, size, offset, e);
+                    result(Err(e.raw_os_error().unwrap()));
+                    return;
+                }
+            }
+        }
+        data.truncate(filled);
+
+        result(Ok(&data));
+    }
+
+    fn statfs(&self, _req: RequestInfo, path: &Path) -> ResultStatfs {
+        debug!("statfs: {:?}", path);
+        let source = match libc_wrappers::statvfs(self.source_dir.clone()) {
+            Ok(source) => source,
+            Err(e) => {
+                error!(
+                    "statvfs({:?}): {}",
+                    self.source_dir,
+                    io::Error::from_raw_os_error(e)
+                );
+                return Err(e);
+            }
+        };
+        let mut statfs = Statfs {
+            blocks: source.f_blocks,
+            bfree: source.f_bfree,
+            bavail: source.f_bavail,
+            files: source.f_files,
+            ffree: source.f_ffree,
+            bsize: source.f_bsize as u32,
+            namelen: source.f_namemax as u32,
+            frsize: source.f_frsize as u32,
+        };
+        if !self.options.statfs_with_cache {
+            return Ok(statfs);
+        }
+        match libc_wrappers::statvfs(self.cache_dir.clone()) {
+            Ok(cache) if cache.f_fsid != source.f_fsid => {
+                // Counted in fragments of the source filesystem
+                let scale = |count: u64| count * cache.f_frsize / source.f_frsize.max(1);
+                statfs.blocks += scale(cache.f_blocks);
+                statfs.bfree += scale(cache.f_bfree);
+                statfs.bavail += scale(cache.f_bavail);
+                statfs.files += cache.f_files;
+                statfs.ffree += cache.f_ffree;
+            }
+            // Already counted
+            Ok(_) => (),
+            Err(e) => warn!(
+                "statvfs({:?}): {}",
+                self.cache_dir,
+                io::Error::from_raw_os_error(e)
+            ),
+        }
+        Ok(statfs)
+    }
+
+    fn access(&self, _req: RequestInfo, path: &Path, mask: u32) -> ResultEmpty {
+        debug!("access: {:?} {:#o}", path, mask);
+
+        let resolved = self.resolve(path)?;
+        let mut mask = mask as libc::c_int;
+        if mask & libc::W_OK != 0 {
+            self.check_modification(&resolved, Modification::Content)?;
+            if self.overlay.is_some() && !resolved.upper {
+                // Copied to the upper directory to be written
+                mask &= !libc::W_OK;
+            }
+        }
+        libc_wrappers::access(self.attributes_path(&resolved), mask)
+    }
+
+    // Unless mounted read-write or with an upper directory, every modification fails with `EROFS`
+
+    fn chmod(&self, _req: RequestInfo, path: &Path, _fh: Option<u64>, mode: u32) -> ResultEmpty {
+        debug!("chmod: {:?} {:#o}", path, mode);
+        let resolved = self.resolve_modified(path, Modification::Attributes)?;
+        let target = self.modified_attributes_path(path, &resolved)?;
+        if let Err(e) = libc_wrappers::chmod(target, mode as libc::mode_t) {
+            error!("chmod({:?}): {}", path, io::Error::from_raw_os_error(e));
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn chown(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        _fh: Option<u64>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> ResultEmpty {
+        debug!("chown: {:?} {:?}:{:?}", path, uid, gid);
+        let resolved = self.resolve_modified(path, Modification::Attributes)?;
+        let target = self.modified_attributes_path(path, &resolved)?;
+        // `-1` leaves the ID unchanged
+        let uid = uid.unwrap_or(u32::MAX) as libc::uid_t;
+        let gid = gid.unwrap_or(u32::MAX) as libc::gid_t;
+        if let Err(e) = libc_wrappers::lchown(target, uid, gid) {
+            error!("chown({:?}): {}", path, io::Error::from_raw_os_error(e));
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn truncate(&self, _req: RequestInfo, path: &Path, fh: Option<u64>, size: u64) -> ResultEmpty {
+        debug!("truncate: {:?} to {:#x}", path, size);
+        let mut resolved = self.resolve_modified(path, Modification::Content)?;
+        if fh.is_none() && self.truncate_pending(path, size) {
+            return Ok(());
+        }
+        if fh.is_none() && self.overlay.is_none() && resolved.access_type != AccessType::PassThrough
+        {
+            let status = self.pm.status(
+                &self.source_path(&resolved.source),
+                &resolved.real,
+                &resolved.access_type,
+            );
+            if status != ProjectionStatus::Done || self.pending.lock().unwrap().contains_key(path) {
+                // Not generating it here (nor hashing its source file), which would hold up every request
+                self.truncate_later(path, resolved, size);
+                return Ok(());
+            }
+        }
+        if fh.is_none() {
+            resolved = self.hashed(resolved);
+        }
+        let truncated = match fh {
+            // This waits for a deferred opening, which is rare: truncating through an open file which wasn't generated yet
+            Some(fh) => libc_wrappers::ftruncate(self.real_fh(fh)?, size as libc::off64_t),
+            None if self.overlay.is_some() => {
+                let real = self.copy_up(self.overlay.as_ref().unwrap(), path, &resolved)?;
+                if self.truncate_pending(path, size) {
+                    // Copied once generated
+                    return Ok(());
+                }
+                libc_wrappers::truncate(real, size as libc::off64_t)
+            }
+            None => {
+                if resolved.access_type != AccessType::PassThrough {
+                    self.pm
+                        .materialize(&resolved.source, &resolved.access_type, self)?;
+                }
+                libc_wrappers::truncate(resolved.real.clone(), size as libc::off64_t)
+            }
+        };
+        if let Err(e) = truncated {
+            error!("truncate({:?}): {}", path, io::Error::from_raw_os_error(e));
+            return Err(e);
+        }
+        match fh.and_then(|fh| self.opened_fh(fh)) {
+            Some(fh) => self.mark_modified(fh),
+            // The source directory is left untouched
+            None if self.overlay.is_some() => (),
+            None => {
+                self.reverse_later(resolved.source, resolved.real, resolved.access_type);
+            }
+        }
+        Ok(())
+    }
+
+    fn utimens(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        _fh: Option<u64>,
+        atime: Option<Timespec>,
+        mtime: Option<Timespec>,
+    ) -> ResultEmpty {
+        debug!("utimens: {:?} {:?} {:?}", path, atime, mtime);
+        let resolved = self.resolve_modified(path, Modification::Attributes)?;
+        let target = self.modified_attributes_path(path, &resolved)?;
+        let timespec = |time: Option<Timespec>| match time {
+            Some(time) => libc::timespec {
+                tv_sec: time.sec as libc::time_t,
+                tv_nsec: time.nsec as libc::c_long,
+            },
+            None => libc::timespec {
+                tv_sec: 0,
+                tv_nsec: libc::UTIME_OMIT,
+            },
+        };
+        let times = [timespec(atime), timespec(mtime)];
+        if let Err(e) = libc_wrappers::utimensat(target, &times) {
+            error!("utimens({:?}): {}", path, io::Error::from_raw_os_error(e));
+            return Err(e);
+        }
+        if let Some(pending) = self.pending.lock().unwrap().get_mut(path) {
+            // Set again once its copy is written
+            pending.times = Some(times);
+        }
+        Ok(())
+    }
+
+    fn mknod(
+        &self,
+        _req: RequestInfo,
+        parent: &Path,
+        name: &OsStr,
+        _mode: u32,
+        _rdev: u32,
+    ) -> ResultEntry {
+        debug!("mknod: {:?}/{:?}", parent, name);
+        Err(self.unsupported())
+    }
+
+    fn mkdir(&self, _req: RequestInfo, parent: &Path, name: &OsStr, mode: u32) -> ResultEntry {
+        debug!("mkdir: {:?}/{:?} {:#o}", parent, name, mode);
+
+        let mkdir = |real: &OsString| match libc_wrappers::mkdir(real.clone(), mode as libc::mode_t)
+        {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                error!("mkdir({:?}): {}", real, io::Error::from_raw_os_error(e));
+                Err(e)
+            }
+        };
+        let real = match &self.overlay {
+            Some(overlay) => {
+                let (real, replaced) = self.new_upper(overlay, parent, name)?;
+                mkdir(&real)?;
+                if replaced {
+                    // The content of the deleted directory of the lower layer doesn't reappear
+                    if let Err(e) = overlay.make_opaque(&parent.join(name)) {
+                        return Err(upper_error("mark as opaque", &parent.join(name), e));
+                    }
+                }
+                real
+            }
+            None => {
+                let partial = self.new_source(parent, name)?;
+                let real = self.source_path(&partial);
+                mkdir(&real)?;
+                self.apply_change(Change::Created {
+                    path: partial,
+                    is_dir: true,
+                });
+                real
+            }
+        };
+        Ok((TTL, self.source_attr(real)?))
+    }
+
+    fn unlink(&self, _req: RequestInfo, parent: &Path, name: &OsStr) -> ResultEmpty {
+        debug!("unlink: {:?}/{:?}", parent, name);
+        let partial = parent.join(name);
+        let resolved = self.resolve_modified(&partial, Modification::Name)?;
+        if let Some(overlay) = &self.overlay {
+            if resolved.upper {
+                if let Err(e) = libc_wrappers::unlink(resolved.real.clone()) {
+                    error!(
+                        "unlink({:?}): {}",
+                        resolved.real,
+                        io::Error::from_raw_os_error(e)
+                    );
+                    return Err(e);
+                }
+                self.move_pending(&partial, None);
+            }
+            if self.in_lower(overlay, &partial) {
+                overlay
+                    .whiteout(&partial)
+                    .map_err(|e| upper_error("whiteout", &partial, e))?;
+            }
+            return Ok(());
+        }
+        // Removing a projected file removes its source file
+        let real = self.source_path(&resolved.source);
+        if let Err(e) = libc_wrappers::unlink(real.clone()) {
+            error!("unlink({:?}): {}", real, io::Error::from_raw_os_error(e));
+            return Err(e);
+        }
+        self.apply_change(Change::Removed {
+            path: resolved.source,
+            is_dir: false,
+        });
+        Ok(())
+    }
+
+    fn rmdir(&self, _req: RequestInfo, parent: &Path, name: &OsStr) -> ResultEmpty {
+        debug!("rmdir: {:?}/{:?}", parent, name);
+        let partial = parent.join(name);
+        let resolved = self.resolve_modified(&partial, Modification::Name)?;
+        if let Some(overlay) = &self.overlay {
+            if self.source_attr(resolved.real.clone())?.kind != FileType::Directory {
+                return Err(libc::ENOTDIR);
+            }
+            // Empty as it appears in the mount
+            let entries = self.list_dir(&partial)?;
+            if entries
+                .iter()
+                .any(|entry| entry.name != "." && entry.name != "..")
+            {
+                return Err(libc::ENOTEMPTY);
+            }
+            if resolved.upper {
+                // Only the marks are left in it
+                fs::remove_dir_all(&resolved.real)
+                    .map_err(|e| upper_error("remove", &partial, e))?;
+                self.move_pending(&partial, None);
+            }
+            if self.in_lower(overlay, &partial) {
+                overlay
+                    .whiteout(&partial)
+                    .map_err(|e| upper_error("whiteout", &partial, e))?;
+            }
+            return Ok(());
+        }
+        let real = self.source_path(&resolved.source);
+        if let Err(e) = libc_wrappers::rmdir(real.clone()) {
+            error!("rmdir({:?}): {}", real, io::Error::from_raw_os_error(e));
+            return Err(e);
+        }
+        self.apply_change(Change::Removed {
+            path: resolved.source,
+            is_dir: true,
+        });
+        Ok(())
+    }
+
+    fn symlink(
+        &self,
+        _req: RequestInfo,
+        parent: &Path,
+        name: &OsStr,
+        _target: &Path,
+    ) -> ResultEntry {
+        debug!("symlink: {:?}/{:?}", parent, name);
+        Err(self.unsupported())
+    }
+
+    fn rename(
+        &self,
+        _req: RequestInfo,
+        parent: &Path,
+        name: &OsStr,
+        newparent: &Path,
+        newname: &OsStr,
+    ) -> ResultEmpty {
+        debug!(
+            "rename: {:?}/{:?} -> {:?}/{:?}",
+            parent, name, newparent, newname
+        );
+        let from = parent.join(name);
+        let resolved = self.resolve_modified(&from, Modification::Name)?;
+        if let Some(overlay) = &self.overlay {
+            let in_lower = self.in_lower(overlay, &from);
+            if in_lower && self.source_attr(resolved.real.clone())?.kind == FileType::Directory {
+                // Like overlayfs, which lets the caller copy the directory instead
+                return Err(libc::EXDEV);
+            }
+            let from_real = self.copy_up(overlay, &from, &resolved)?;
+            let is_dir = self.source_attr(from_real.clone())?.kind == FileType::Directory;
+            let to = newparent.join(newname);
+            let (to_real, replaced) = self.new_upper(overlay, newparent, newname)?;
+            if let Err(e) = libc_wrappers::rename(from_real.clone(), to_real.clone()) {
+                error!(
+                    "rename({:?}, {:?}): {}",
+                    from_real,
+                    to_real,
+                    io::Error::from_raw_os_error(e)
+                );
+                if replaced {
+                    if let Err(e) = overlay.whiteout(&to) {
+                        error!("can't delete {:?} again: {}", to, e);
+                    }
+                }
+                return Err(e);
+            }
+            self.move_pending(&from, Some(&to));
+            if is_dir && (replaced || self.in_lower(overlay, &to)) {
+                // Like mkdir(): the content of the directory of the lower layer doesn't reappear
+                overlay
+                    .make_opaque(&to)
+                    .map_err(|e| upper_error("mark as opaque", &to, e))?;
+            }
+            if in_lower {
+                overlay
+                    .whiteout(&from)
+                    .map_err(|e| upper_error("whiteout", &from, e))?;
+            }
+            return Ok(());
+        }
+        // Renaming a projected file renames its source file
+        let to = match resolved.access_type {
+            AccessType::Projected => {
+                self.new_source(newparent, &self.renamed_source(&resolved, newname)?)?
+            }
+            _ => self.new_source(newparent, newname)?,
+        };
+        let from_real = self.source_path(&resolved.source);
+        let is_dir = fsop::getattr(from_real.clone())?.kind == FileType::Directory;
+        if let Err(e) = libc_wrappers::rename(from_real.clone(), self.source_path(&to)) {
+            error!(
+                "rename({:?}, {:?}): {}",
+                from_real,
+                to,
+                io::Error::from_raw_os_error(e)
+            );
+            return Err(e);
+        }
+        self.apply_change(Change::Renamed {
+            from: resolved.source,
+            to: to,
+            is_dir: is_dir,
+        });
+        Ok(())
+    }
+
+    fn link(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        _newparent: &Path,
+        _newname: &OsStr,
+    ) -> ResultEntry {
+        debug!("link: {:?}", path);
+        Err(self.unsupported())
+    }
+
+    fn create(
+        &self,
+        _req: RequestInfo,
+        parent: &Path,
+        name: &OsStr,
+        mode: u32,
+        flags: u32,
+    ) -> ResultCreate {
+        debug!(
+            "create: {:?}/{:?} (mode={:#o}, flags={:#x})",
+            parent, name, mode, flags
+        );
+
+        let (partial, real) = match &self.overlay {
+            Some(overlay) => (None, self.new_upper(overlay, parent, name)?.0),
+            None => {
+                let partial = self.new_source(parent, name)?;
+                let real = self.source_path(&partial);
+                (Some(partial), real)
+            }
+        };
+        let fh =
+            match libc_wrappers::create(real.clone(), flags as libc::c_int, mode as libc::mode_t) {
+                Ok(fh) => fh,
+                Err(e) => {
+                    error!("create({:?}): {}", real, io::Error::from_raw_os_error(e));
+                    return Err(e);
+                }
+            };
+        let attr = match libc_wrappers::fstat(fh) {
+            Ok(stat) => br::stat_to_fuse(stat),
+            Err(e) => {
+                error!("fstat({:?}): {}", real, io::Error::from_raw_os_error(e));
+                let _ = libc_wrappers::close(fh);
+                return Err(e);
+            }
+        };
+        if let Some(partial) = partial {
+            self.written.lock().unwrap().insert(fh, partial.clone());
+            self.apply_change(Change::Created {
+                path: partial,
+                is_dir: false,
+            });
+        }
+        Ok(CreatedEntry {
+            ttl: TTL,
+            attr: attr,
+            fh: fh,
+            flags: flags,
+        })
+    }
+
+    fn write(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        fh: u64,
+        offset: u64,
+        data: Vec<u8>,
+        _flags: u32,
+    ) -> ResultWrite {
+        debug!("write: {:?} {:#x} @ {:#x}", path, data.len(), offset);
+        // Only files opened for writing, which `open()` has checked, can be written
+        let fh = self.real_fh(fh)?;
+        let mut file = unsafe { UnmanagedFile::new(fh) };
+
+        if let Err(e) = file.seek(SeekFrom::Start(offset)) {
+            error!("seek({:?}, {}): {}", path, offset, e);
+            return Err(e.raw_os_error().unwrap());
+        }
+        match file.write(&data) {
+            Ok(written) => {
+                self.mark_modified(fh);
+                Ok(written as u32)
+            }
+            Err(e) => {
+                error!("write {:?}, {:#x} @ {:#x}: {}", path, data.len(), offset, e);
+                Err(e.raw_os_error().unwrap())
+            }
+        }
+    }
+
+    fn setxattr(
+        &self,
+        _req: RequestInfo,
+        path: &Path,
+        name: &OsStr,
+        _value: &[u8],
+        _flags: u32,
+        _position: u32,
+    ) -> ResultEmpty {
+        debug!("setxattr: {:?} {:?}", path, name);
+        Err(self.unsupported())
+    }
+
+    fn removexattr(&self, _req: RequestInfo, path: &Path, name: &OsStr) -> ResultEmpty {
+        debug!("removexattr: {:?} {:?}", path, name);
+        Err(self.unsupported())
+    }
+}
+
+/// The `.` and `..` entries of a directory listing
+fn dot_entries() -> Vec<DirectoryEntry> {
+    vec![
+        DirectoryEntry {
+            name: OsString::from("."),
+            kind: FileType::Directory,
+        },
+        DirectoryEntry {
+            name: OsString::from(".."),
+            kind: FileType::Directory,
+        },
+    ]
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AccessType {
+    Projected,
+    PassThrough,
+    /// The sidecar file generated by the `n`th sidecar specification
+    Sidecar(usize),
+}
@@ -0,0 +1,287 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use time::Timespec;
+
+use crate::config::{CollisionPolicy, PassThroughMode};
+use crate::fsop;
+
+use super::manager::ProjectionManager;
+use super::{AccessType, ProjectionResolver};
+
+/// The name in the mount of a regular file of the source directory
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum MountName {
+    Projected(OsString),
+    PassThrough(OsString),
+    /// Hidden by a name collision
+    Hidden,
+}
+
+/// The names in the mount of the regular files of a source directory, valid while the directory keeps the same modification time
+pub(super) struct DirectoryNames {
+    pub(super) mtime: Timespec,
+    /// By source file name
+    pub(super) names: HashMap<OsString, MountName>,
+    /// The names of the sidecar files hidden by another entry of the directory with the same name
+    pub(super) hidden_sidecars: HashSet<OsString>,
+}
+
+/// Makes `name` unique among `taken` by adding a `~N` suffix before its extension if needed, and adds it to `taken`
+pub(super) fn unique_name(taken: &mut HashSet<OsString>, name: OsString) -> OsString {
+    let mut unique = name.clone();
+    let mut n = 2;
+    while taken.contains(&unique) {
+        let path = Path::new(&name);
+        unique = path.file_stem().unwrap_or_default().to_os_string();
+        unique.push(format!("~{}", n));
+        if let Some(extension) = path.extension() {
+            unique.push(".");
+            unique.push(extension);
+        }
+        n += 1;
+    }
+    taken.insert(unique.clone());
+    unique
+}
+
+impl ProjectionManager {
+    /// Records (without projecting) the projection of the file at `partial`, and returns the relative partial path of the projected file,
+    /// or `None` if it's hidden by a name collision
+    pub(super) fn map_name<T: AsRef<Path>>(
+        &self,
+        partial: T,
+        resolver: &dyn ProjectionResolver,
+    ) -> Option<PathBuf> {
+        let source_partial = partial.as_ref();
+        match self.projected_name(source_partial, resolver) {
+            Some(dest_partial) => {
+                self.insert(
+                    OsString::from(source_partial),
+                    OsString::from(&dest_partial),
+                );
+                Some(dest_partial)
+            }
+            None => {
+                self.projection
+                    .write()
+                    .unwrap()
+                    .remove_by_left(&OsString::from(source_partial));
+                None
+            }
+        }
+    }
+
+    /// The relative partial path of the projected file of `partial`, or `None` if it's hidden by a name collision
+    pub(super) fn projected_name(
+        &self,
+        partial: &Path,
+        resolver: &dyn ProjectionResolver,
+    ) -> Option<PathBuf> {
+        match self.mount_name(partial, resolver) {
+            Some(MountName::Projected(name)) => Some(partial.with_file_name(name)),
+            Some(MountName::Hidden) => None,
+            // Not a regular file (e.g. a symbolic link), which doesn't take part in name collisions
+            _ => Some(PathBuf::from(
+                self.spec.convert_filename(partial.as_os_str()),
+            )),
+        }
+    }
+
+    /// The name in the mount of the regular file `partial` of the source directory, with the name collisions resolved.
+    /// The names of a directory are computed again when the directory changes.
+    pub(super) fn mount_name(
+        &self,
+        partial: &Path,
+        resolver: &dyn ProjectionResolver,
+    ) -> Option<MountName> {
+        let dir_partial = partial.parent()?;
+        self.with_names(dir_partial, resolver, |listed| {
+            listed.names.get(partial.file_name()?).cloned()
+        })?
+    }
+
+    /// Whether the sidecar file `partial` (in the mount) is hidden by another entry with the same name
+    pub(super) fn sidecar_hidden(&self, partial: &Path, resolver: &dyn ProjectionResolver) -> bool {
+        let dir_partial = match partial.parent() {
+            Some(dir_partial) => dir_partial,
+            None => return false,
+        };
+        self.with_names(dir_partial, resolver, |listed| match partial.file_name() {
+            Some(name) => listed.hidden_sidecars.contains(name),
+            None => false,
+        })
+        .unwrap_or(false)
+    }
+
+    /// Calls `f` with the names in the mount of the regular files of the source directory `dir_partial`
+    pub(super) fn with_names<R, F: FnOnce(&DirectoryNames) -> R>(
+        &self,
+        dir_partial: &Path,
+        resolver: &dyn ProjectionResolver,
+        f: F,
+    ) -> Option<R> {
+        let mtime = fsop::getattr(resolver.source(dir_partial)).ok()?.mtime;
+        {
+            let names = self.names.lock().unwrap();
+            if let Some(listed) = names
+                .get(dir_partial)
+                .filter(|listed| listed.mtime == mtime)
+            {
+                return Some(f(listed));
+            }
+        }
+        // Not holding the lock while listing the directory, which would hold up every lookup
+        let listed = self.list_names(dir_partial, mtime, resolver);
+        let mut names = self.names.lock().unwrap();
+        names.insert(dir_partial.to_owned(), listed);
+        Some(f(&names[dir_partial]))
+    }
+
+    /// Computes the names in the mount of the regular files of the source directory `dir_partial`, resolving the name collisions
+    pub(super) fn list_names(
+        &self,
+        dir_partial: &Path,
+        mtime: Timespec,
+        resolver: &dyn ProjectionResolver,
+    ) -> DirectoryNames {
+        let mut names = HashMap::new();
+        let mut hidden_sidecars = HashSet::new();
+        let entries = match fs::read_dir(resolver.source(dir_partial)) {
+            Ok(entries) => entries,
+            Err(_) => {
+                return DirectoryNames {
+                    mtime: mtime,
+                    names: names,
+                    hidden_sidecars: hidden_sidecars,
+                }
+            }
+        };
+        // The files (by name, and whether they are projected) which would appear under each name
+        let mut candidates: BTreeMap<OsString, Vec<(OsString, bool)>> = BTreeMap::new();
+        // The names of the other entries, which sidecar files can't take
+        let mut occupied = HashSet::new();
+        for entry in entries.filter_map(Result::ok) {
+            let name = entry.file_name();
+            let partial = dir_partial.join(&name);
+            if self
+                .spec
+                .is_excluded(partial.strip_prefix("/").unwrap_or(&partial))
+            {
+                continue;
+            }
+            if !entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+                occupied.insert(name);
+                continue;
+            }
+            if self.spec.show_original() {
+                occupied.insert(name.clone());
+            }
+            let candidate = if self.access_type(entry.path()) == AccessType::Projected {
+                let dest_partial = PathBuf::from(self.spec.convert_filename(partial.as_os_str()));
+                (dest_partial.file_name().unwrap().to_owned(), true)
+            } else if self.spec.passthrough() == PassThroughMode::Show {
+                (name.clone(), false)
+            } else {
+                names.insert(name.clone(), MountName::PassThrough(name));
+                continue;
+            };
+            candidates
+                .entry(candidate.0)
+                .or_default()
+                .push((name, candidate.1));
+        }
+        let mut taken: HashSet<OsString> = candidates.keys().cloned().collect();
+        let policy = self.spec.collision();
+        for (candidate, mut files) in candidates {
+            files.sort();
+            // The one keeping the name
+            let keeper = match policy {
+                CollisionPolicy::First => 0,
+                _ => files
+                    .iter()
+                    .position(|(_, projected)| !projected)
+                    .unwrap_or(0),
+            };
+            let collides = files.len() > 1;
+            let mut resolution = Vec::new();
+            for (i, (name, projected)) in files.into_iter().enumerate() {
+                // A file provided as-is can lose its name as well, but can't be renamed
+                let mount_name = if !projected {
+                    if i == keeper || policy == CollisionPolicy::Extension {
+                        MountName::PassThrough(name.clone())
+                    } else {
+                        MountName::Hidden
+                    }
+                } else if collides && policy == CollisionPolicy::Extension {
+                    let extended = match Path::new(&candidate).extension() {
+                        Some(extension) => {
+                            fsop::append_suffix(&name, &format!(".{}", extension.to_string_lossy()))
+                                .into_os_string()
+                        }
+                        None => name.clone(),
+                    };
+                    MountName::Projected(unique_name(&mut taken, extended))
+                } else if i == keeper {
+                    MountName::Projected(candidate.clone())
+                } else if policy == CollisionPolicy::Suffix {
+                    MountName::Projected(unique_name(&mut taken, candidate.clone()))
+                } else {
+                    MountName::Hidden
+                };
+                if collides {
+                    resolution.push(format!("{:?} -> {:?}", name, mount_name));
+                }
+                names.insert(name, mount_name);
+            }
+            if collides {
+                warn!(
+                    "name collision on {:?} in {:?} ({:?} policy): {}",
+                    candidate,
+                    dir_partial,
+                    policy,
+                    resolution.join(", ")
+                );
+            }
+        }
+        for mount_name in names.values() {
+            match mount_name {
+                MountName::Projected(name) => occupied.insert(name.clone()),
+                MountName::PassThrough(name)
+                    if self.spec.passthrough() == PassThroughMode::Show =>
+                {
+                    occupied.insert(name.clone())
+                }
+                _ => false,
+            };
+        }
+        // The real entries keep their name over the sidecar files
+        for (name, mount_name) in &names {
+            if *mount_name == MountName::Hidden {
+                continue;
+            }
+            let partial = dir_partial.join(name);
+            for sidecar in self.spec.sidecars() {
+                if !sidecar.applies_to(&partial) {
+                    continue;
+                }
+                let sidecar_name =
+                    fsop::append_suffix(Path::new(name), &sidecar.suffix).into_os_string();
+                if occupied.contains(&sidecar_name) {
+                    warn!(
+                        "name collision on {:?} in {:?}: the sidecar file of {:?} is hidden by the source entry",
+                        sidecar_name, dir_partial, name
+                    );
+                    hidden_sidecars.insert(sidecar_name);
+                }
+            }
+        }
+        DirectoryNames {
+            mtime: mtime,
+            names: names,
+            hidden_sidecars: hidden_sidecars,
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use fuse_mt::*;
+
+use crate::fsop;
+use crate::libc_bridge::libc;
+use crate::libc_bridge::libc_wrappers;
+
+use super::manager::STREAM_POLL_INTERVAL;
+use super::{AccessType, ProjectionFS, Resolved};
+
+/// Flag of the `open` reply making the kernel bypass its page cache and the file size for reads (see `fuse_kernel.h`)
+pub(super) const FOPEN_DIRECT_IO: u32 = 1 << 0;
+
+/// The first file handle of the files whose opening is deferred (see `DeferredOpen`), above those of the real files
+pub(super) const FIRST_DEFERRED_FH: u64 = 1 << 32;
+
+/// A projected or sidecar file opened through `open()`
+pub(super) struct OpenFile {
+    /// The real path in the cache directory
+    pub(super) real: OsString,
+    pub(super) access_type: AccessType,
+    /// Whether it was still being generated when opened
+    pub(super) streaming: bool,
+    /// Whether it has been modified through this handle (see `ProjectedWrites`)
+    pub(super) modified: bool,
+}
+
+/// A projected or sidecar file which wasn't generated yet when opened.
+/// `open()` runs on the thread dispatching every request, so it only returns a deferred file handle,
+/// and the file is generated and opened on its first read or write, which run on the threads of `--threads`.
+pub(super) enum DeferredOpen {
+    /// Not opened yet, with the path in the mount, the flags and the resolved file from `open()`
+    Pending(PathBuf, u32, Resolved),
+    /// With the real file handle
+    Opened(u64),
+    Failed(libc::c_int),
+}
+
+impl ProjectionFS {
+    /// Starts generating the projected or sidecar file `resolved` in the background if it's not up to date,
+    /// and waits until it can be opened. Returns whether it's still being generated (under the name `fsop::temp_path(dest)`).
+    /// Without background workers, generates it first instead. Fails if the generation fails.
+    pub(super) fn stream(&self, resolved: &Resolved) -> Result<bool, libc::c_int> {
+        let workers = match &self.workers {
+            Some(workers) => workers,
+            None => {
+                self.pm
+                    .materialize(&resolved.source, &resolved.access_type, self)?;
+                return Ok(false);
+            }
+        };
+        let source = self.source_path(&resolved.source);
+        let dest = &resolved.real;
+        let temp = fsop::temp_path(dest);
+        if self.pm.claim(&source, dest, &resolved.access_type)? {
+            let pm = Arc::clone(&self.pm);
+            let access_type = resolved.access_type.clone();
+            let dest = dest.clone();
+            // Ahead of the background generations, since it's being waited for
+            workers.submit_first(move || {
+                pm.run_generation(&source, &dest, &access_type);
+                pm.touch(&dest, &access_type, false);
+            });
+        }
+        let mut generating = self.pm.generating.lock().unwrap();
+        // An outdated `dest` is still there while it's generated again
+        while generating.contains(dest) && !Path::new(&temp).exists() {
+            generating = self
+                .pm
+                .generated
+                .wait_timeout(generating, STREAM_POLL_INTERVAL)
+                .unwrap()
+                .0;
+        }
+        if generating.contains(dest) {
+            return Ok(true);
+        }
+        drop(generating);
+        // The outdated `dest` may still be there
+        if self.pm.failures.lock().unwrap().contains_key(dest) {
+            return Err(libc::EIO);
+        }
+        Ok(false)
+    }
+
+    /// Opens the file `path` of the mount (resolved as `resolved`), generating it first if needed
+    pub(super) fn open_now(&self, path: &Path, flags: u32, mut resolved: Resolved) -> ResultOpen {
+        // Deferred by `open()` meanwhile
+        self.wait_modified(path);
+        let flags_c = flags as libc::c_int;
+        let writing = flags_c & libc::O_ACCMODE != libc::O_RDONLY || flags_c & libc::O_TRUNC != 0;
+        resolved = self.hashed(resolved);
+        if let (Some(overlay), true) = (&self.overlay, writing) {
+            resolved = Resolved {
+                access_type: AccessType::PassThrough,
+                real: self.copy_up(overlay, path, &resolved)?,
+                source: path.to_owned(),
+                upper: true,
+            };
+            // Copied once generated
+            self.wait_modified(path);
+        }
+        // Not evicted from its generation until it's open, and then until it's closed
+        let pinned = if resolved.access_type != AccessType::PassThrough {
+            Some(self.pm.pin(&resolved.real, &resolved.access_type))
+        } else {
+            None
+        };
+        if writing && resolved.access_type != AccessType::PassThrough {
+            // Modified in the cache (see `ProjectedWrites::Cache`), once completely generated
+            self.pm
+                .materialize(&resolved.source, &resolved.access_type, self)?;
+        }
+        let streaming = !writing
+            && resolved.access_type != AccessType::PassThrough
+            && self.stream(&resolved)?;
+        let opened = if streaming {
+            // The open file follows the temporary file when it's renamed, once complete
+            match libc_wrappers::open(fsop::temp_path(&resolved.real), flags as libc::c_int) {
+                Err(libc::ENOENT) => {
+                    libc_wrappers::open(resolved.real.clone(), flags as libc::c_int)
+                }
+                opened => opened,
+            }
+        } else {
+            libc_wrappers::open(resolved.real.clone(), flags as libc::c_int)
+        };
+        if let (Ok(fh), true, false) = (opened, writing, resolved.upper) {
+            self.written
+                .lock()
+                .unwrap()
+                .insert(fh, resolved.source.clone());
+        }
+        match opened {
+            Ok(fh) if resolved.access_type != AccessType::PassThrough => {
+                // Records its size, now that it's generated
+                self.pm.touch(&resolved.real, &resolved.access_type, false);
+                if let Some(pinned) = pinned {
+                    pinned.keep();
+                }
+                self.open_files.lock().unwrap().insert(
+                    fh,
+                    OpenFile {
+                        real: resolved.real,
+                        access_type: resolved.access_type,
+                        streaming: streaming,
+                        modified: flags_c & libc::O_TRUNC != 0,
+                    },
+                );
+                if streaming {
+                    debug!("{:?} is still being generated; streaming it", path);
+                    Ok((fh, FOPEN_DIRECT_IO))
+                } else {
+                    Ok((fh, flags))
+                }
+            }
+            Ok(fh) => Ok((fh, flags)),
+            Err(e) => {
+                error!("open({:?}): {}", path, io::Error::from_raw_os_error(e));
+                Err(e)
+            }
+        }
+    }
+
+    /// The real file handle of `fh`, which is opened first if its opening is deferred (see `DeferredOpen`)
+    pub(super) fn real_fh(&self, fh: u64) -> Result<u64, libc::c_int> {
+        let deferred = match self.deferred.lock().unwrap().get(&fh) {
+            Some(deferred) => Arc::clone(deferred),
+            None => return Ok(fh),
+        };
+        let mut deferred = deferred.lock().unwrap();
+        let opened = match &*deferred {
+            DeferredOpen::Opened(fh) => return Ok(*fh),
+            DeferredOpen::Failed(e) => return Err(*e),
+            DeferredOpen::Pending(path, flags, resolved) => {
+                self.open_now(path, *flags, resolved.clone())
+            }
+        };
+        *deferred = match opened {
+            Ok((fh, _)) => DeferredOpen::Opened(fh),
+            Err(e) => DeferredOpen::Failed(e),
+        };
+        opened.map(|(fh, _)| fh)
+    }
+
+    /// The real file handle of `fh` if it's opened, without waiting for a deferred opening (see `DeferredOpen`)
+    pub(super) fn opened_fh(&self, fh: u64) -> Option<u64> {
+        let deferred = match self.deferred.lock().unwrap().get(&fh) {
+            Some(deferred) => Arc::clone(deferred),
+            None => return Some(fh),
+        };
+        // Being opened if locked
+        let opened = match deferred.try_lock().as_deref() {
+            Ok(DeferredOpen::Opened(fh)) => Some(*fh),
+            _ => None,
+        };
+        opened
+    }
+
+    /// Records that the projected or sidecar file open as `fh` has been modified, if it is one
+    pub(super) fn mark_modified(&self, fh: u64) {
+        if let Some(file) = self.open_files.lock().unwrap().get_mut(&fh) {
+            file.modified = true;
+        }
+    }
+}
@@ -0,0 +1,890 @@
+use super::*;
+use crate::config;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Source and cache directories in a temporary directory, removed when dropped
+struct TestDirs {
+    root: PathBuf,
+    /// The number of scripts written so far, to name the next one
+    scripts: AtomicUsize,
+}
+
+impl TestDirs {
+    fn new() -> TestDirs {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let root = std::env::temp_dir().join(format!(
+            "projfs-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(root.join("source")).unwrap();
+        fs::create_dir_all(root.join("cache")).unwrap();
+        TestDirs {
+            root: root,
+            scripts: AtomicUsize::new(0),
+        }
+    }
+
+    fn source_file(&self, partial: &str, content: &[u8]) {
+        let path = self.root.join("source").join(partial);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    /// Writes a shell script running `body`, and returns its path
+    fn script(&self, body: &str) -> String {
+        let path = self.root.join(format!(
+            "script-{}.sh",
+            self.scripts.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn mount(&self, config: &str, options: Options) -> ProjectionFS {
+        let path = self.root.join("config.yml");
+        fs::write(&path, config).unwrap();
+        ProjectionFS::new(
+            self.root.join("source").into_os_string(),
+            self.root.join("cache").into_os_string(),
+            config::load(path.as_os_str()).unwrap(),
+            options,
+        )
+    }
+}
+
+impl Drop for TestDirs {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn options() -> Options {
+    Options {
+        workers: 1,
+        prefetch: Prefetch::Listing,
+        watch: false,
+        cache_layout: CacheLayout::Path,
+        symlinks: SymlinkMode::Keep,
+        statfs_with_cache: false,
+        read_write: false,
+        projected_writes: ProjectedWrites::Deny,
+        upper: None,
+        cache_max_size: None,
+        gc_interval: None,
+    }
+}
+
+fn request() -> RequestInfo {
+    RequestInfo {
+        unique: 0,
+        uid: 0,
+        gid: 0,
+        pid: 0,
+    }
+}
+
+fn projecting_with(command: &str) -> String {
+    format!(
+        "mime_types: [audio/flac]\nname_mapping: ogg\nprojection_command: {}\n",
+        command
+    )
+}
+
+/// Reads the open file `fh` until the end
+fn read_all(fs: &ProjectionFS, path: &str, fh: u64) -> Result<Vec<u8>, libc::c_int> {
+    let mut content = Vec::new();
+    loop {
+        let mut read = Err(0);
+        fs.read(
+            request(),
+            Path::new(path),
+            fh,
+            content.len() as u64,
+            4096,
+            |data| read = data.map(|data| data.to_vec()),
+        );
+        match read? {
+            ref data if data.is_empty() => return Ok(content),
+            data => content.extend(data),
+        }
+    }
+}
+
+/// Lists the directory `path` of the mount, without `.` and `..`
+fn list(fs: &ProjectionFS, path: &str) -> Vec<OsString> {
+    let (fh, _) = fs.opendir(request(), Path::new(path), 0).unwrap();
+    let entries = fs.readdir(request(), Path::new(path), fh).unwrap();
+    fs.releasedir(request(), Path::new(path), fh, 0).unwrap();
+    let mut names: Vec<OsString> = entries
+        .into_iter()
+        .map(|entry| entry.name)
+        .filter(|name| name != "." && name != "..")
+        .collect();
+    names.sort();
+    names
+}
+
+/// Lists the root of a mount of `song.flac` next to a real `song.ogg` (with the `policy` collision policy),
+/// along with the source file of `song.ogg` in the mount
+fn collide(policy: &str) -> (Vec<OsString>, PathBuf) {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"");
+    test.source_file("song.ogg", b"");
+    let config = format!(
+        "{}collision: {}\n",
+        projecting_with("cp {input} {output}"),
+        policy
+    );
+    let fs = test.mount(
+        &config,
+        Options {
+            workers: 0,
+            ..options()
+        },
+    );
+    let names = list(&fs, "/");
+    (names, fs.resolve("/song.ogg").unwrap().source)
+}
+
+#[test]
+fn collisions_with_files_provided_as_is_are_resolved() {
+    let names = |names: &[&str]| names.iter().map(OsString::from).collect::<Vec<_>>();
+    assert_eq!(
+        collide("first"),
+        (names(&["song.ogg"]), PathBuf::from("/song.flac"))
+    );
+    assert_eq!(
+        collide("passthrough"),
+        (names(&["song.ogg"]), PathBuf::from("/song.ogg"))
+    );
+    assert_eq!(
+        collide("suffix"),
+        (
+            names(&["song.ogg", "song~2.ogg"]),
+            PathBuf::from("/song.ogg")
+        )
+    );
+    assert_eq!(
+        collide("extension"),
+        (
+            names(&["song.flac.ogg", "song.ogg"]),
+            PathBuf::from("/song.ogg")
+        )
+    );
+}
+
+#[test]
+fn renamed_projected_files_rename_their_source_file() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"");
+    let fs = test.mount(
+        &projecting_with("cp {input} {output}"),
+        Options {
+            workers: 0,
+            ..options()
+        },
+    );
+    let resolved = fs.resolve("/song.ogg").unwrap();
+    let renamed = |name: &str| fs.renamed_source(&resolved, OsStr::new(name));
+    assert_eq!(renamed("01 song.ogg"), Ok(OsString::from("01 song.flac")));
+    assert_eq!(
+        renamed("01 song.flac.ogg"),
+        Ok(OsString::from("01 song.flac"))
+    );
+    assert_eq!(renamed("01 song.mp3"), Err(libc::EINVAL));
+}
+
+#[test]
+fn cache_writes_are_denied_with_the_content_layout() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"flac");
+    let writable = |cache_layout| Options {
+        workers: 0,
+        read_write: true,
+        projected_writes: ProjectedWrites::Cache,
+        cache_layout: cache_layout,
+        ..options()
+    };
+    let config = projecting_with("cp {input} {output}");
+    let fs = test.mount(&config, writable(CacheLayout::Content));
+    let open = |fs: &ProjectionFS| {
+        fs.open(request(), Path::new("/song.ogg"), libc::O_WRONLY as u32)
+            .map(|(fh, _)| fs.release(request(), Path::new("/song.ogg"), fh, 0, 0, false))
+    };
+    assert_eq!(open(&fs), Err(libc::EACCES));
+    let fs = test.mount(&config, writable(CacheLayout::Path));
+    assert_eq!(open(&fs), Ok(Ok(())));
+}
+
+#[test]
+fn modified_files_are_converted_back_in_the_background() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"flac");
+    let script = test.script("sleep 0.2; cp \"$1\" \"$2\"");
+    let config = format!(
+        "{}reverse_command: {} {{input}} {{output}}\n",
+        projecting_with("cp {input} {output}"),
+        script
+    );
+    let fs = test.mount(
+        &config,
+        Options {
+            read_write: true,
+            projected_writes: ProjectedWrites::Source,
+            ..options()
+        },
+    );
+    let path = Path::new("/song.ogg");
+    let flags = (libc::O_WRONLY | libc::O_TRUNC) as u32;
+    let (fh, _) = fs.open(request(), path, flags).unwrap();
+    fs.write(request(), path, fh, 0, b"edited".to_vec(), 0)
+        .unwrap();
+    fs.release(request(), path, fh, 0, 0, false).unwrap();
+    let source = test.root.join("source/song.flac");
+    // Not converted back yet
+    assert_eq!(fs::read(&source).unwrap(), b"flac");
+    for _ in 0..50 {
+        if fs::read(&source).unwrap() == b"edited" {
+            return;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    panic!("{:?} hasn't been converted back", source);
+}
+
+#[test]
+fn files_being_generated_are_read_completely() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"");
+    let script = test.script("printf abc; sleep 0.2; printf def");
+    let fs = test.mount(
+        &projecting_with(&format!("{} {{input}}", script)),
+        options(),
+    );
+    let (fh, _) = fs
+        .open(request(), Path::new("/song.ogg"), libc::O_RDONLY as u32)
+        .unwrap();
+    assert_eq!(read_all(&fs, "/song.ogg", fh), Ok(b"abcdef".to_vec()));
+    fs.release(request(), Path::new("/song.ogg"), fh, 0, 0, false)
+        .unwrap();
+}
+
+#[test]
+fn files_not_generated_yet_are_opened_on_first_read() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"");
+    let fs = test.mount(&projecting_with("echo {input}"), options());
+    let (fh, _) = fs
+        .open(request(), Path::new("/song.ogg"), libc::O_RDONLY as u32)
+        .unwrap();
+    assert!(fh >= FIRST_DEFERRED_FH);
+    assert!(!test.root.join("cache/song.ogg").exists());
+    let expected = format!("{}\n", test.root.join("source/song.flac").display());
+    assert_eq!(read_all(&fs, "/song.ogg", fh), Ok(expected.into_bytes()));
+    fs.release(request(), Path::new("/song.ogg"), fh, 0, 0, false)
+        .unwrap();
+    assert!(fs.deferred.lock().unwrap().is_empty());
+}
+
+#[test]
+fn source_files_are_hashed_only_when_opened() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"flac");
+    let fs = test.mount(
+        &projecting_with("cp {input} {output}"),
+        Options {
+            workers: 0,
+            cache_layout: CacheLayout::Content,
+            ..options()
+        },
+    );
+    let source = test.root.join("source/song.flac");
+    let hash = || {
+        fs.pm
+            .index
+            .content_hash(source.as_os_str(), &fs::metadata(&source).unwrap())
+    };
+    fs.getattr(request(), Path::new("/song.ogg"), None).unwrap();
+    assert_eq!(hash(), None);
+    let (fh, _) = fs
+        .open(request(), Path::new("/song.ogg"), libc::O_RDONLY as u32)
+        .unwrap();
+    // Opened on the first read (see `DeferredOpen`)
+    assert_eq!(hash(), None);
+    assert_eq!(read_all(&fs, "/song.ogg", fh), Ok(b"flac".to_vec()));
+    assert!(hash().is_some());
+    fs.release(request(), Path::new("/song.ogg"), fh, 0, 0, false)
+        .unwrap();
+}
+
+#[test]
+fn reading_a_file_whose_generation_fails_is_an_error() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"");
+    let script = test.script("printf abc; sleep 0.2; exit 1");
+    let fs = test.mount(
+        &projecting_with(&format!("{} {{input}}", script)),
+        options(),
+    );
+    let (fh, _) = fs
+        .open(request(), Path::new("/song.ogg"), libc::O_RDONLY as u32)
+        .unwrap();
+    assert_eq!(read_all(&fs, "/song.ogg", fh), Err(libc::EIO));
+    fs.release(request(), Path::new("/song.ogg"), fh, 0, 0, false)
+        .unwrap();
+}
+
+#[test]
+fn directories_renamed_onto_deleted_directories_hide_their_content() {
+    let test = TestDirs::new();
+    test.source_file("Old/song.flac", b"");
+    test.source_file("New/cover.jpg", b"");
+    let fs = test.mount(
+        &projecting_with("cp {input} {output}"),
+        Options {
+            workers: 0,
+            upper: Some(test.root.join("upper").into_os_string()),
+            ..options()
+        },
+    );
+    fs.unlink(request(), Path::new("/New"), OsStr::new("cover.jpg"))
+        .unwrap();
+    fs.rmdir(request(), Path::new("/"), OsStr::new("New"))
+        .unwrap();
+    fs.mkdir(request(), Path::new("/"), OsStr::new("Created"), 0o755)
+        .unwrap();
+    fs.rename(
+        request(),
+        Path::new("/"),
+        OsStr::new("Created"),
+        Path::new("/"),
+        OsStr::new("New"),
+    )
+    .unwrap();
+    assert_eq!(
+        list(&fs, "/"),
+        vec![OsString::from("New"), OsString::from("Old")]
+    );
+    assert!(list(&fs, "/New").is_empty());
+    assert_eq!(
+        fs.getattr(request(), Path::new("/New/cover.jpg"), None)
+            .err(),
+        Some(libc::ENOENT)
+    );
+}
+
+#[test]
+fn directories_are_shown_once_files_are_added_deeper() {
+    let test = TestDirs::new();
+    fs::create_dir_all(test.root.join("source/album/disc")).unwrap();
+    let fs = test.mount(
+        &format!(
+            "{}prune_empty_dirs: true\n",
+            projecting_with("cp {input} {output}")
+        ),
+        Options {
+            workers: 0,
+            ..options()
+        },
+    );
+    fs.init(request()).unwrap();
+    assert!(list(&fs, "/").is_empty());
+
+    // Without watching, only the mtime of the directory the file is added in changes
+    test.source_file("album/disc/song.flac", b"");
+    assert_eq!(list(&fs, "/"), vec![OsString::from("album")]);
+}
+
+/// Generates `song.ogg` with the cache layout `cache_layout`, and collects the garbage in the cache with a new instance
+/// (as with `--gc-only`) after an orphan file is added to the cache. Returns the files left in the cache.
+fn collect_with(cache_layout: CacheLayout) -> Vec<PathBuf> {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"flac");
+    let config = projecting_with("cp {input} {output}");
+    let options = || Options {
+        workers: 0,
+        cache_layout: cache_layout,
+        ..options()
+    };
+    let fs = test.mount(&config, options());
+    let (fh, _) = fs.open(request(), Path::new("/song.ogg"), 0).unwrap();
+    assert_eq!(read_all(&fs, "/song.ogg", fh), Ok(b"flac".to_vec()));
+    fs.release(request(), Path::new("/song.ogg"), fh, 0, 0, false)
+        .unwrap();
+    fs.destroy(request());
+    fs::write(test.root.join("cache/orphan.ogg"), b"").unwrap();
+
+    let reclaimed = test.mount(&config, options()).collect_garbage();
+    assert_eq!(reclaimed.files, 1);
+    let mut left = Vec::new();
+    let mut dirs = vec![test.root.join("cache")];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(dir).unwrap().map(Result::unwrap) {
+            let partial =
+                Path::new("/").join(entry.path().strip_prefix(test.root.join("cache")).unwrap());
+            if entry.file_type().unwrap().is_dir() {
+                dirs.push(entry.path());
+            } else if !Index::is_index_file(&partial) {
+                left.push(partial);
+            }
+        }
+    }
+    left
+}
+
+#[test]
+fn garbage_collection_keeps_the_files_in_the_index() {
+    assert_eq!(
+        collect_with(CacheLayout::Path),
+        vec![PathBuf::from("/song.ogg")]
+    );
+    let left = collect_with(CacheLayout::Content);
+    assert_eq!(left.len(), 1);
+    assert_ne!(left[0], PathBuf::from("/orphan.ogg"));
+}
+
+/// Opens the file `path` of the mount and reads it, closing it unless `keep_open`
+fn read_file(fs: &ProjectionFS, path: &str, keep_open: bool) -> Vec<u8> {
+    let (fh, _) = fs.open(request(), Path::new(path), 0).unwrap();
+    let content = read_all(fs, path, fh).unwrap();
+    if !keep_open {
+        fs.release(request(), Path::new(path), fh, 0, 0, false)
+            .unwrap();
+    }
+    content
+}
+
+/// Mounts `a.flac`, `b.flac` and `c.flac` (4 bytes each) with the configuration `config` and the maximum cache size `max_size`,
+/// reads them in order (keeping `a.ogg` open if `keep_open`), and returns the projected files left in the cache
+fn evict_with(config: &str, max_size: Option<u64>, keep_open: bool) -> Vec<&'static str> {
+    let test = TestDirs::new();
+    for name in &["a", "b", "c"] {
+        test.source_file(&format!("{}.flac", name), b"flac");
+    }
+    let fs = test.mount(
+        config,
+        Options {
+            workers: 0,
+            cache_max_size: max_size,
+            ..options()
+        },
+    );
+    fs.init(request()).unwrap();
+    read_file(&fs, "/a.ogg", keep_open);
+    read_file(&fs, "/b.ogg", false);
+    read_file(&fs, "/c.ogg", false);
+    ["a.ogg", "b.ogg", "c.ogg"]
+        .iter()
+        .cloned()
+        .filter(|name| test.root.join("cache").join(name).exists())
+        .collect()
+}
+
+#[test]
+fn the_least_recently_used_files_are_evicted() {
+    let config = projecting_with("cp {input} {output}");
+    assert_eq!(evict_with(&config, Some(8), false), vec!["b.ogg", "c.ogg"]);
+    let config = format!("{}quota: 8\n", config);
+    assert_eq!(evict_with(&config, None, false), vec!["b.ogg", "c.ogg"]);
+}
+
+#[test]
+fn open_files_are_not_evicted() {
+    let config = projecting_with("cp {input} {output}");
+    assert_eq!(evict_with(&config, Some(8), true), vec!["a.ogg", "c.ogg"]);
+    // Even larger than the cache, a file is kept from its generation until it's closed
+    assert_eq!(evict_with(&config, Some(2), false), vec!["c.ogg"]);
+}
+
+#[test]
+fn files_removed_from_the_cache_are_generated_again() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"flac");
+    let fs = test.mount(
+        &projecting_with("cp {input} {output}"),
+        Options {
+            workers: 0,
+            ..options()
+        },
+    );
+    assert_eq!(read_file(&fs, "/song.ogg", false), b"flac");
+    fs::remove_file(test.root.join("cache/song.ogg")).unwrap();
+    assert!(fs.getattr(request(), Path::new("/song.ogg"), None).is_ok());
+    assert_eq!(read_file(&fs, "/song.ogg", false), b"flac");
+}
+
+#[test]
+fn cache_files_missing_from_the_index_are_reused_only_with_the_current_rule() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"flac");
+    let mount = |command: &str| {
+        test.mount(
+            &projecting_with(command),
+            Options {
+                workers: 0,
+                ..options()
+            },
+        )
+    };
+    let fs = mount("cp {input} {output}");
+    assert_eq!(read_file(&fs, "/song.ogg", false), b"flac");
+    // As after a crash before the index is saved
+    drop(fs);
+    let fs = mount("cp {input} {output}");
+    assert_eq!(read_file(&fs, "/song.ogg", false), b"flac");
+    drop(fs);
+    let script = test.script("echo ogg > \"$2\"");
+    let fs = mount(&format!("{} {{input}} {{output}}", script));
+    assert_eq!(read_file(&fs, "/song.ogg", false), b"ogg\n");
+}
+
+#[test]
+fn outdated_files_are_kept_until_generated_again() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"flac");
+    let script = test.script("sleep 0.2; grep -q bad \"$1\" && exit 1; cp \"$1\" \"$2\"");
+    let fs = test.mount(
+        &projecting_with(&format!("{} {{input}} {{output}}", script)),
+        options(),
+    );
+    assert_eq!(read_file(&fs, "/song.ogg", false), b"flac");
+
+    // Read while generated again, it's the new content
+    test.source_file("song.flac", b"flac, again");
+    assert_eq!(read_file(&fs, "/song.ogg", false), b"flac, again");
+
+    // A failed generation leaves the last generated file
+    test.source_file("song.flac", b"bad");
+    let (fh, _) = fs.open(request(), Path::new("/song.ogg"), 0).unwrap();
+    assert_eq!(read_all(&fs, "/song.ogg", fh), Err(libc::EIO));
+    fs.release(request(), Path::new("/song.ogg"), fh, 0, 0, false)
+        .unwrap();
+    assert_eq!(
+        fs::read(test.root.join("cache/song.ogg")).unwrap(),
+        b"flac, again"
+    );
+}
+
+#[test]
+fn temporary_files_are_removed_when_mounting() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"flac");
+    let fs = test.mount(&projecting_with("cp {input} {output}"), options());
+    let temp = fsop::temp_path(&test.root.join("cache/song.ogg").into_os_string());
+    fs::create_dir_all(test.root.join("cache")).unwrap();
+    fs::write(&temp, b"").unwrap();
+    fs.init(request()).unwrap();
+    assert!(!Path::new(&temp).exists());
+}
+
+#[test]
+fn files_not_generated_yet_are_truncated_in_the_background() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"flac");
+    let script = test.script("sleep 0.5; cp \"$1\" \"$2\"");
+    let fs = test.mount(
+        &projecting_with(&format!("{} {{input}} {{output}}", script)),
+        Options {
+            read_write: true,
+            projected_writes: ProjectedWrites::Cache,
+            ..options()
+        },
+    );
+    let path = Path::new("/song.ogg");
+    let started = std::time::Instant::now();
+    fs.truncate(request(), path, None, 2).unwrap();
+    assert!(started.elapsed() < std::time::Duration::from_millis(500));
+    assert_eq!(fs.getattr(request(), path, None).unwrap().1.size, 2);
+    assert_eq!(read_file(&fs, "/song.ogg", false), b"fl");
+}
+
+#[test]
+fn files_hidden_by_a_collision_are_not_generated() {
+    let test = TestDirs::new();
+    test.source_file("a.flac", b"flac");
+    test.source_file("a.wav", b"wave!");
+    let fs = test.mount(
+        "mime_types: [audio]\nname_mapping: ogg\nprojection_command: cp {input} {output}\ncollision: first\n",
+        options(),
+    );
+    assert_eq!(list(&fs, "/"), vec![OsString::from("a.ogg")]);
+    // Queued after the generations queued by the listing, with a single worker
+    let (done, generated) = std::sync::mpsc::channel();
+    fs.workers
+        .as_ref()
+        .unwrap()
+        .submit(move || done.send(()).unwrap());
+    generated.recv().unwrap();
+    assert_eq!(fs::read(test.root.join("cache/a.ogg")).unwrap(), b"flac");
+    assert_eq!(
+        fs.getattr(request(), Path::new("/a.wav"), None).err(),
+        Some(libc::ENOENT)
+    );
+}
+
+#[test]
+fn files_not_generated_yet_are_copied_up_in_the_background() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"flac");
+    let script = test.script("sleep 0.5; cp \"$1\" \"$2\"");
+    let fs = test.mount(
+        &projecting_with(&format!("{} {{input}} {{output}}", script)),
+        Options {
+            upper: Some(test.root.join("upper").into_os_string()),
+            ..options()
+        },
+    );
+    let started = std::time::Instant::now();
+    fs.chmod(request(), Path::new("/song.ogg"), None, 0o600)
+        .unwrap();
+    fs.rename(
+        request(),
+        Path::new("/"),
+        OsStr::new("song.ogg"),
+        Path::new("/"),
+        OsStr::new("renamed.ogg"),
+    )
+    .unwrap();
+    fs.truncate(request(), Path::new("/renamed.ogg"), None, 2)
+        .unwrap();
+    assert!(started.elapsed() < std::time::Duration::from_millis(500));
+    let (_, attr) = fs
+        .getattr(request(), Path::new("/renamed.ogg"), None)
+        .unwrap();
+    assert_eq!((attr.perm, attr.size), (0o600, 2));
+    assert_eq!(read_file(&fs, "/renamed.ogg", false), b"fl");
+    assert_eq!(list(&fs, "/"), vec![OsString::from("renamed.ogg")]);
+}
+
+#[test]
+fn originals_are_shown_next_to_projected_files() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"flac");
+    let config = format!(
+        "{}passthrough: hide\nshow_original: true\n",
+        projecting_with("printf ogg")
+    );
+    let fs = test.mount(
+        &config,
+        Options {
+            workers: 0,
+            ..options()
+        },
+    );
+    assert_eq!(
+        list(&fs, "/"),
+        vec![OsString::from("song.flac"), OsString::from("song.ogg")]
+    );
+    assert_eq!(read_file(&fs, "/song.flac", false), b"flac");
+    assert_eq!(read_file(&fs, "/song.ogg", false), b"ogg");
+}
+
+#[test]
+fn sidecar_files_are_generated_next_to_source_files() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"flac");
+    test.source_file("notes.txt", b"notes");
+    test.source_file("notes.txt.json", b"real");
+    let config = format!(
+        "{}sidecars:\n  - suffix: .json\n    command: printf json\n    mime_types: [audio, text]\n",
+        projecting_with("cp {input} {output}")
+    );
+    let fs = test.mount(
+        &config,
+        Options {
+            workers: 0,
+            ..options()
+        },
+    );
+    let names: Vec<OsString> = ["notes.txt", "notes.txt.json", "song.flac.json", "song.ogg"]
+        .iter()
+        .map(OsString::from)
+        .collect();
+    assert_eq!(list(&fs, "/"), names);
+    assert_eq!(read_file(&fs, "/song.flac.json", false), b"json");
+    // Hidden by the real file with the same name
+    assert_eq!(read_file(&fs, "/notes.txt.json", false), b"real");
+}
+
+#[test]
+fn files_are_projected_on_open_rather_than_when_listed() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"flac");
+    let marker = test.root.join("converted");
+    let script = test.script(&format!("touch {}; cat \"$1\"", marker.display()));
+    let fs = test.mount(
+        &projecting_with(&format!("{} {{input}}", script)),
+        Options {
+            workers: 0,
+            ..options()
+        },
+    );
+    assert_eq!(list(&fs, "/"), vec![OsString::from("song.ogg")]);
+    fs.getattr(request(), Path::new("/song.ogg"), None).unwrap();
+    assert!(!marker.exists());
+    assert_eq!(read_file(&fs, "/song.ogg", false), b"flac");
+    assert!(marker.exists());
+}
+
+#[test]
+fn files_not_projected_yet_have_placeholder_attributes() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"0123456789");
+    let config = format!(
+        "{}placeholder_size: \"20%\"\n",
+        projecting_with("printf ogg")
+    );
+    let fs = test.mount(
+        &config,
+        Options {
+            workers: 0,
+            ..options()
+        },
+    );
+    let source = fs::metadata(test.root.join("source/song.flac")).unwrap();
+    let (ttl, attr) = fs.getattr(request(), Path::new("/song.ogg"), None).unwrap();
+    assert_eq!(ttl, PLACEHOLDER_TTL);
+    assert_eq!((attr.size, attr.blocks), (2, 0));
+    assert_eq!(attr.mtime.sec, source.mtime());
+    read_file(&fs, "/song.ogg", false);
+    let (ttl, attr) = fs.getattr(request(), Path::new("/song.ogg"), None).unwrap();
+    assert_eq!(ttl, TTL);
+    assert_eq!(attr.size, 3);
+}
+
+#[test]
+fn projected_files_are_found_before_their_directory_is_listed() {
+    let test = TestDirs::new();
+    test.source_file("album/song.flac", b"flac");
+    let fs = test.mount(
+        &projecting_with("cp {input} {output}"),
+        Options {
+            workers: 0,
+            ..options()
+        },
+    );
+    assert!(fs
+        .getattr(request(), Path::new("/album/song.ogg"), None)
+        .is_ok());
+    assert_eq!(
+        fs.getattr(request(), Path::new("/album/other.ogg"), None)
+            .err(),
+        Some(libc::ENOENT)
+    );
+    assert_eq!(read_file(&fs, "/album/song.ogg", false), b"flac");
+}
+
+/// The value of the extended attribute `name` of the file `path` of the mount
+fn xattr(fs: &ProjectionFS, path: &str, name: &str) -> Result<Vec<u8>, libc::c_int> {
+    match fs.getxattr(request(), Path::new(path), OsStr::new(name), 4096)? {
+        Xattr::Data(data) => Ok(data),
+        Xattr::Size(_) => panic!("no data returned"),
+    }
+}
+
+#[test]
+fn projected_files_have_the_extended_attributes_of_their_source_and_virtual_ones() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"flac");
+    let source = test.root.join("source/song.flac");
+    fsop::setxattr(source.into_os_string(), OsStr::new("user.comment"), b"live").unwrap();
+    let fs = test.mount(
+        &projecting_with("cp {input} {output}"),
+        Options {
+            workers: 0,
+            ..options()
+        },
+    );
+    assert_eq!(
+        xattr(&fs, "/song.ogg", "user.comment"),
+        Ok(b"live".to_vec())
+    );
+    assert_eq!(
+        xattr(&fs, "/song.ogg", "user.projfs.source"),
+        Ok(b"/song.flac".to_vec())
+    );
+    assert_eq!(
+        xattr(&fs, "/song.ogg", "user.projfs.rule"),
+        Ok(b"projection".to_vec())
+    );
+    assert_eq!(
+        xattr(&fs, "/song.ogg", "user.projfs.status"),
+        Ok(b"pending".to_vec())
+    );
+    read_file(&fs, "/song.ogg", false);
+    assert_eq!(
+        xattr(&fs, "/song.ogg", "user.projfs.status"),
+        Ok(b"done".to_vec())
+    );
+    let names = match fs.listxattr(request(), Path::new("/song.ogg"), 4096) {
+        Ok(Xattr::Data(names)) => String::from_utf8(names).unwrap(),
+        _ => panic!("no names returned"),
+    };
+    let names: Vec<&str> = names.split('\0').collect();
+    assert!(names.contains(&"user.comment"));
+    assert!(names.contains(&"user.projfs.source"));
+    // Passed through files have no virtual attributes
+    test.source_file("cover.jpg", b"");
+    assert_eq!(
+        xattr(&fs, "/cover.jpg", "user.projfs.source"),
+        Err(libc::ENODATA)
+    );
+    assert_eq!(
+        fs.setxattr(
+            request(),
+            Path::new("/song.ogg"),
+            OsStr::new("user.comment"),
+            b"studio",
+            0,
+            0
+        ),
+        Err(libc::EROFS)
+    );
+}
+
+#[test]
+fn read_only_mounts_report_the_source_filesystem_and_reject_modifications() {
+    let test = TestDirs::new();
+    test.source_file("song.flac", b"flac");
+    let fs = test.mount(
+        &projecting_with("cp {input} {output}"),
+        Options {
+            workers: 0,
+            ..options()
+        },
+    );
+    let source = libc_wrappers::statvfs(test.root.join("source").into_os_string()).unwrap();
+    let statfs = fs.statfs(request(), Path::new("/")).unwrap();
+    assert_eq!(
+        (statfs.blocks, statfs.files),
+        (source.f_blocks, source.f_files)
+    );
+    let access = |path: &str, mask: libc::c_int| fs.access(request(), Path::new(path), mask as u32);
+    assert_eq!(access("/song.ogg", libc::R_OK), Ok(()));
+    assert_eq!(access("/song.ogg", libc::W_OK), Err(libc::EROFS));
+    assert_eq!(access("/missing.ogg", libc::R_OK), Err(libc::ENOENT));
+    let root = Path::new("/");
+    assert_eq!(
+        fs.mkdir(request(), root, OsStr::new("album"), 0o755).err(),
+        Some(libc::EROFS)
+    );
+    assert_eq!(
+        fs.unlink(request(), root, OsStr::new("song.ogg")),
+        Err(libc::EROFS)
+    );
+    assert_eq!(
+        fs.rename(
+            request(),
+            root,
+            OsStr::new("song.ogg"),
+            root,
+            OsStr::new("renamed.ogg")
+        ),
+        Err(libc::EROFS)
+    );
+    assert_eq!(list(&fs, "/"), vec![OsString::from("song.ogg")]);
+}
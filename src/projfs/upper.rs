@@ -0,0 +1,281 @@
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use fuse_mt::*;
+
+use crate::libc_bridge::libc;
+use crate::libc_bridge::libc_wrappers;
+use crate::overlay::Overlay;
+
+use super::manager::ProjectionStatus;
+use super::write::PendingModification;
+use super::{dot_entries, AccessType, ProjectionFS, Resolved};
+
+impl ProjectionFS {
+    /// Copies the entry `path` of the mount (resolved as `resolved`) to the upper directory, unless it's already there, to be modified there.
+    /// Returns its real path in the upper directory.
+    pub(super) fn copy_up(
+        &self,
+        overlay: &Overlay,
+        path: &Path,
+        resolved: &Resolved,
+    ) -> Result<OsString, libc::c_int> {
+        if resolved.upper {
+            return Ok(resolved.real.clone());
+        }
+        let generated = resolved.access_type == AccessType::PassThrough
+            || self.pm.status(
+                &self.source_path(&resolved.source),
+                &resolved.real,
+                &resolved.access_type,
+            ) == ProjectionStatus::Done;
+        let resolved = &self.hashed(resolved.clone());
+        let upper = overlay.real(path);
+        let errno = |e: io::Error| {
+            error!("can't copy {:?} to {:?}: {}", path, upper, e);
+            e.raw_os_error().unwrap_or(libc::EIO)
+        };
+        if path.parent().is_none() {
+            // The root directory is the upper directory itself
+            fs::create_dir_all(&upper).map_err(errno)?;
+            return Ok(upper);
+        }
+        overlay.create_parents(path).map_err(errno)?;
+        if !generated {
+            return self.copy_up_later(path, resolved.clone(), upper);
+        }
+        // Not evicted before it's copied
+        let _pinned = if resolved.access_type != AccessType::PassThrough {
+            let pinned = self.pm.pin(&resolved.real, &resolved.access_type);
+            self.pm
+                .materialize(&resolved.source, &resolved.access_type, self)?;
+            Some(pinned)
+        } else {
+            None
+        };
+        match self.source_attr(resolved.real.clone())?.kind {
+            FileType::Directory => fs::create_dir(&upper).map_err(errno)?,
+            FileType::RegularFile => {
+                fs::copy(&resolved.real, &upper).map_err(errno)?;
+            }
+            FileType::Symlink => {
+                let target = fs::read_link(&resolved.real).map_err(errno)?;
+                std::os::unix::fs::symlink(target, &upper).map_err(errno)?;
+                debug!("{:?} copied to the upper directory", path);
+                return Ok(upper);
+            }
+            _ => return Err(libc::EPERM),
+        }
+        // The copy has the mode of the entry in the mount
+        let perm = self.source_attr(self.attributes_path(resolved))?.perm;
+        libc_wrappers::chmod(upper.clone(), perm as libc::mode_t)?;
+        debug!("{:?} copied to the upper directory", path);
+        Ok(upper)
+    }
+
+    /// Copies the projected or sidecar file `path` of the mount (resolved as `resolved`), which isn't generated yet, to `upper`
+    /// in the upper directory, where it's created right away: it's generated and its content written by a worker.
+    /// Until then, it has the placeholder size, its attributes can be changed, and it's opened once its content is complete.
+    pub(super) fn copy_up_later(
+        &self,
+        path: &Path,
+        resolved: Resolved,
+        upper: OsString,
+    ) -> Result<OsString, libc::c_int> {
+        let errno = |e: io::Error| {
+            error!("can't copy {:?} to {:?}: {}", path, upper, e);
+            e.raw_os_error().unwrap_or(libc::EIO)
+        };
+        let (_, attr) = self.placeholder_attr(path, &resolved)?;
+        let copy = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&upper)
+            .map_err(errno)?;
+        // The copy has the mode of the entry in the mount
+        libc_wrappers::chmod(upper.clone(), attr.perm as libc::mode_t)?;
+        let ino = copy.metadata().map_err(errno)?.ino();
+        self.pending.lock().unwrap().insert(
+            path.to_owned(),
+            PendingModification {
+                size: attr.size,
+                truncated: false,
+                times: None,
+                copy: Some(ino),
+            },
+        );
+        let fs = self.clone();
+        self.run_later("copy", move || fs.complete_copy(copy, ino, resolved));
+        debug!("{:?} copied to the upper directory, once generated", path);
+        Ok(upper)
+    }
+
+    /// Generates the projected or sidecar file `resolved`, and writes it into `copy`, its copy in the upper directory (see `copy_up_later()`)
+    pub(super) fn complete_copy(&self, mut copy: fs::File, ino: u64, resolved: Resolved) {
+        let resolved = self.hashed(resolved);
+        let copied = {
+            // Not evicted before it's copied
+            let _pinned = self.pm.pin(&resolved.real, &resolved.access_type);
+            self.pm
+                .materialize(&resolved.source, &resolved.access_type, self)
+                .and_then(|()| {
+                    let mut content = fs::File::open(&resolved.real)
+                        .map_err(|e| e.raw_os_error().unwrap_or(libc::EIO))?;
+                    io::copy(&mut content, &mut copy)
+                        .map_err(|e| e.raw_os_error().unwrap_or(libc::EIO))
+                })
+        };
+        let overlay = self.overlay.as_ref().unwrap();
+        let mut pending = self.pending.lock().unwrap();
+        self.modified.notify_all();
+        let path = match pending
+            .iter()
+            .find(|(_, pending)| pending.copy == Some(ino))
+        {
+            Some((path, _)) => path.clone(),
+            // Removed or replaced meanwhile
+            None => return,
+        };
+        let modification = pending.remove(&path).unwrap();
+        let upper = overlay.real(&path);
+        let completed = copied.and_then(|_| {
+            if modification.truncated {
+                copy.set_len(modification.size)
+                    .map_err(|e| e.raw_os_error().unwrap_or(libc::EIO))?;
+            }
+            match &modification.times {
+                Some(times) => libc_wrappers::utimensat(upper.clone(), times),
+                None => Ok(()),
+            }
+        });
+        if let Err(e) = completed {
+            // Like a failed copy: the entry of the lower layer is left (unless the copy has been renamed)
+            error!(
+                "can't copy {:?} to the upper directory: {}",
+                resolved.real,
+                io::Error::from_raw_os_error(e)
+            );
+            if let Err(e) = fs::remove_file(&upper) {
+                error!("can't remove the incomplete copy {:?}: {}", upper, e);
+            }
+        }
+    }
+
+    /// Prepares the upper directory for the new entry `name` of the directory `parent` of the mount.
+    /// Returns its real path in the upper directory, and whether it replaces a deleted entry of the lower layer.
+    pub(super) fn new_upper(
+        &self,
+        overlay: &Overlay,
+        parent: &Path,
+        name: &OsStr,
+    ) -> Result<(OsString, bool), libc::c_int> {
+        if Overlay::is_reserved(name) {
+            debug!("{:?} is reserved for the upper directory", name);
+            return Err(libc::EINVAL);
+        }
+        let resolved = self.resolve(parent)?;
+        if self.source_attr(resolved.real.clone())?.kind != FileType::Directory {
+            return Err(libc::ENOTDIR);
+        }
+        self.copy_up(overlay, parent, &resolved)?;
+        let partial = parent.join(name);
+        match overlay.remove_whiteout(&partial) {
+            Ok(replaced) => Ok((overlay.real(&partial), replaced)),
+            Err(e) => {
+                error!("can't restore {:?} in the upper directory: {}", partial, e);
+                Err(e.raw_os_error().unwrap_or(libc::EIO))
+            }
+        }
+    }
+
+    /// Whether the entry `path` of the mount exists in the lower layer, i.e. without the upper directory
+    pub(super) fn in_lower(&self, overlay: &Overlay, path: &Path) -> bool {
+        if overlay.hides(path) {
+            return false;
+        }
+        match self.resolve_lower(path) {
+            // Files provided as-is are resolved whether they exist or not
+            Ok(resolved) => {
+                resolved.access_type != AccessType::PassThrough
+                    || self.source_attr(resolved.real).is_ok()
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// The real path of the directory `path` of the lower layer, if it isn't hidden by the upper directory
+    pub(super) fn lower_dir(&self, path: &Path) -> Option<OsString> {
+        if let Some(overlay) = &self.overlay {
+            if overlay.is_opaque(path) {
+                return None;
+            }
+        }
+        let resolved = self.resolve_lower(path).ok()?;
+        match self.source_attr(resolved.real.clone()) {
+            Ok(stat) if stat.kind == FileType::Directory => Some(resolved.real),
+            _ => None,
+        }
+    }
+
+    /// Lists the directory `path` of the mount, without an open handle
+    pub(super) fn list_dir(&self, path: &Path) -> ResultReaddir {
+        let entries = match &self.namespace {
+            Some(namespace) => self.readdir_rewritten(namespace, path),
+            None => match self.lower_dir(path) {
+                Some(real) => {
+                    let fh = libc_wrappers::opendir(real)?;
+                    let entries = self.readdir_source(path, fh);
+                    if let Err(e) = libc_wrappers::closedir(fh) {
+                        error!("closedir({:?}): {}", path, io::Error::from_raw_os_error(e));
+                    }
+                    entries
+                }
+                None => Ok(dot_entries()),
+            },
+        };
+        match &self.overlay {
+            Some(overlay) => self.merge_upper(overlay, path, entries),
+            None => entries,
+        }
+    }
+
+    /// Adds the entries of the directory `path` of the upper directory to the listing `lower` of the lower layer,
+    /// removing the entries they shadow and the deleted ones
+    pub(super) fn merge_upper(
+        &self,
+        overlay: &Overlay,
+        path: &Path,
+        lower: ResultReaddir,
+    ) -> ResultReaddir {
+        let mut entries = match lower {
+            Ok(entries) => entries,
+            Err(libc::ENOENT) if overlay.contains(path) => dot_entries(),
+            Err(e) => return Err(e),
+        };
+        let opaque = overlay.is_opaque(path);
+        let (upper_entries, whiteouts) = overlay.list(path);
+        entries.retain(|entry| {
+            entry.name == "."
+                || entry.name == ".."
+                || !(opaque
+                    || whiteouts.contains(&entry.name)
+                    || upper_entries.iter().any(|(name, _)| name == &entry.name))
+        });
+        for (name, kind) in upper_entries {
+            entries.push(DirectoryEntry {
+                name: name,
+                kind: kind,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// Logs the failure to `what` the entry `partial` in the upper directory, and returns its error number
+pub(super) fn upper_error(what: &str, partial: &Path, e: io::Error) -> libc::c_int {
+    error!("can't {} {:?} in the upper directory: {}", what, partial, e);
+    e.raw_os_error().unwrap_or(libc::EIO)
+}
@@ -0,0 +1,302 @@
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use crate::libc_bridge::libc;
+use crate::libc_bridge::libc_wrappers;
+use crate::namespace::Node;
+use crate::watcher::Change;
+
+use super::{AccessType, CacheLayout, ProjectedWrites, ProjectionFS, Resolved};
+
+/// What an operation modifies, to apply `ProjectedWrites`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Modification {
+    Name,
+    Attributes,
+    Content,
+}
+
+/// A modification of a projected or sidecar file which wasn't generated yet, done in the background.
+/// The requests modifying files run on the thread dispatching every request, which mustn't wait for the generation.
+pub(super) struct PendingModification {
+    /// The size of the file, as reported until it's done
+    pub(super) size: u64,
+    /// Whether the file is truncated to `size` once generated
+    pub(super) truncated: bool,
+    /// The access and modification times set meanwhile, set again once its copy is complete
+    pub(super) times: Option<[libc::timespec; 2]>,
+    /// The inode of its copy in the upper directory, for a copy to the upper directory (see `copy_up_later()`)
+    pub(super) copy: Option<u64>,
+}
+
+impl ProjectionFS {
+    /// Checks whether `modification` of the entry `resolved` is allowed (see `ProjectedWrites`)
+    pub(super) fn check_modification(
+        &self,
+        resolved: &Resolved,
+        modification: Modification,
+    ) -> Result<(), libc::c_int> {
+        if !self.writable() {
+            return Err(libc::EROFS);
+        }
+        if self.overlay.is_some() {
+            // Made in the upper directory, whatever the entry is
+            return Ok(());
+        }
+        let allowed = match (&resolved.access_type, self.options.projected_writes) {
+            (AccessType::PassThrough, _) => true,
+            (_, ProjectedWrites::Deny) => false,
+            // The name of a sidecar file follows the one of its source file
+            (AccessType::Sidecar(_), _) if modification == Modification::Name => false,
+            // With the content cache layout, the cache file is shared by every source file with the same content,
+            // so its content can only be modified to be converted back into the source file
+            (access_type, policy) => {
+                modification != Modification::Content
+                    || (policy == ProjectedWrites::Cache && self.pm.layout == CacheLayout::Path)
+                    || self.pm.reversible(access_type)
+            }
+        };
+        if allowed {
+            Ok(())
+        } else {
+            debug!(
+                "modification ({:?}) of {:?} ({:?}) is not allowed",
+                modification, resolved.source, resolved.access_type
+            );
+            Err(libc::EACCES)
+        }
+    }
+
+    /// Resolves the entry `path` of the mount, if `modification` of it is allowed
+    pub(super) fn resolve_modified(
+        &self,
+        path: &Path,
+        modification: Modification,
+    ) -> Result<Resolved, libc::c_int> {
+        if !self.writable() {
+            return Err(libc::EROFS);
+        }
+        if let (Some(namespace), None) = (&self.namespace, &self.overlay) {
+            if let Some(Node::Directory { source: None }) = namespace.lookup(path) {
+                debug!("{:?} only exists in the mount", path);
+                return Err(libc::EPERM);
+            }
+        }
+        let resolved = self.resolve(path)?;
+        self.check_modification(&resolved, modification)?;
+        Ok(resolved)
+    }
+
+    /// The relative partial path in the source directory of the new entry `name` of the directory `parent` of the mount
+    pub(super) fn new_source(&self, parent: &Path, name: &OsStr) -> Result<PathBuf, libc::c_int> {
+        if !self.options.read_write {
+            return Err(libc::EROFS);
+        }
+        let dir_partial = match &self.namespace {
+            Some(namespace) => match namespace.lookup(parent) {
+                Some(Node::Directory {
+                    source: Some(source),
+                }) => source,
+                Some(Node::Directory { source: None }) => {
+                    debug!("{:?} only exists in the mount", parent);
+                    return Err(libc::EPERM);
+                }
+                Some(_) => return Err(libc::ENOTDIR),
+                None => return Err(libc::ENOENT),
+            },
+            None => self.resolve(parent)?.source,
+        };
+        Ok(dir_partial.join(name))
+    }
+
+    /// The name of the source file of the projected file `resolved`, for the projected file to be renamed `name`.
+    /// `name` must have the extension of the projected file, which is replaced with the one of the source file.
+    pub(super) fn renamed_source(
+        &self,
+        resolved: &Resolved,
+        name: &OsStr,
+    ) -> Result<OsString, libc::c_int> {
+        let projected = PathBuf::from(self.pm.spec.convert_filename(resolved.source.as_os_str()));
+        let renamed = Path::new(name);
+        if renamed.extension() != projected.extension() {
+            warn!(
+                "{:?} can't be renamed to {:?}, which doesn't have the extension of projected files",
+                resolved.source, name
+            );
+            return Err(libc::EINVAL);
+        }
+        let stem = renamed.file_stem().ok_or(libc::EINVAL)?;
+        match resolved.source.extension() {
+            // The source extension may already be kept (see `CollisionPolicy::Extension`)
+            Some(extension) if Path::new(stem).extension() != Some(extension) => {
+                let mut source_name = stem.to_os_string();
+                source_name.push(".");
+                source_name.push(extension);
+                Ok(source_name)
+            }
+            _ => Ok(stem.to_os_string()),
+        }
+    }
+
+    /// The error of the modifications which are not supported
+    pub(super) fn unsupported(&self) -> libc::c_int {
+        if self.writable() {
+            libc::EPERM
+        } else {
+            libc::EROFS
+        }
+    }
+
+    /// Whether the mount can be modified, either in the source directory or in the upper directory
+    pub(super) fn writable(&self) -> bool {
+        self.options.read_write || self.overlay.is_some()
+    }
+
+    /// The real path whose attributes are changed to change the ones of the entry `path` of the mount (resolved as `resolved`):
+    /// its copy in the upper directory if there is one, or else the source entry
+    pub(super) fn modified_attributes_path(
+        &self,
+        path: &Path,
+        resolved: &Resolved,
+    ) -> Result<OsString, libc::c_int> {
+        match &self.overlay {
+            Some(overlay) => self.copy_up(overlay, path, resolved),
+            None => Ok(self.source_path(&resolved.source)),
+        }
+    }
+
+    /// Converts the modified projected or sidecar file `real` back into its source file `partial` (see `ProjectionManager::reverse()`),
+    /// and then applies the change of the source file.
+    /// This runs the reverse command, so it's done by a worker (or by a thread of its own without workers), not by a FUSE thread.
+    pub(super) fn reverse_later(&self, partial: PathBuf, real: OsString, access_type: AccessType) {
+        let fs = self.clone();
+        self.run_later("reverse conversion", move || {
+            fs.pm.reverse(&partial, &real, &access_type, &fs);
+            fs.apply_change(Change::Modified(partial));
+        });
+    }
+
+    /// Applies the change of the source file `partial`, modified through the mount, by a worker:
+    /// with a rewritten hierarchy, this runs the layout extractor.
+    pub(super) fn apply_change_later(&self, partial: PathBuf) {
+        let fs = self.clone();
+        self.run_later("change", move || fs.apply_change(Change::Modified(partial)));
+    }
+
+    /// Truncates the projected or sidecar file `path` of the mount (resolved as `resolved`) to `size`,
+    /// once generated by a worker, and then converts it back like `reverse_later()`.
+    /// Until then, it has the size it's truncated to, and it's opened once the truncation is done.
+    pub(super) fn truncate_later(&self, path: &Path, resolved: Resolved, size: u64) {
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if let Some(pending) = pending.get_mut(path) {
+                // Truncated again before the truncation is done
+                pending.size = size;
+                return;
+            }
+            pending.insert(
+                path.to_owned(),
+                PendingModification {
+                    size: size,
+                    truncated: true,
+                    times: None,
+                    copy: None,
+                },
+            );
+        }
+        let fs = self.clone();
+        let path = path.to_owned();
+        self.run_later("truncation", move || {
+            let resolved = fs.hashed(resolved);
+            let generated = fs
+                .pm
+                .materialize(&resolved.source, &resolved.access_type, &fs);
+            let truncated = {
+                let mut pending = fs.pending.lock().unwrap();
+                let size = pending
+                    .remove(&path)
+                    .map(|pending| pending.size)
+                    .unwrap_or(size);
+                fs.modified.notify_all();
+                generated.and_then(|()| {
+                    libc_wrappers::truncate(resolved.real.clone(), size as libc::off64_t)
+                })
+            };
+            if let Err(e) = truncated {
+                error!("truncate({:?}): {}", path, io::Error::from_raw_os_error(e));
+                return;
+            }
+            fs.pm
+                .reverse(&resolved.source, &resolved.real, &resolved.access_type, &fs);
+            fs.apply_change(Change::Modified(resolved.source));
+        });
+    }
+
+    /// Truncates the file `path` of the mount to `size` once its pending modifications are done, if it has any.
+    /// Returns whether it has.
+    pub(super) fn truncate_pending(&self, path: &Path, size: u64) -> bool {
+        match self.pending.lock().unwrap().get_mut(path) {
+            Some(pending) => {
+                pending.size = size;
+                pending.truncated = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the pending modifications of the entry `from` of the mount (and of the entries under it) to `to`,
+    /// replacing those of `to`, or forgets them if `to` is `None`
+    pub(super) fn move_pending(&self, from: &Path, to: Option<&Path>) {
+        let mut pending = self.pending.lock().unwrap();
+        let moved: Vec<PathBuf> = pending
+            .keys()
+            .filter(|path| path.starts_with(from))
+            .cloned()
+            .collect();
+        let moved: Vec<(PathBuf, PendingModification)> = moved
+            .into_iter()
+            .map(|path| {
+                let modification = pending.remove(&path).unwrap();
+                (path, modification)
+            })
+            .collect();
+        if let Some(to) = to {
+            pending.retain(|path, _| !path.starts_with(to));
+            for (path, modification) in moved {
+                let path = match path.strip_prefix(from) {
+                    Ok(rest) if rest != Path::new("") => to.join(rest),
+                    _ => to.to_owned(),
+                };
+                pending.insert(path, modification);
+            }
+        }
+        self.modified.notify_all();
+    }
+
+    /// Waits until the modifications of the file `path` of the mount waiting for it to be generated are done
+    pub(super) fn wait_modified(&self, path: &Path) {
+        let mut pending = self.pending.lock().unwrap();
+        while pending.contains_key(path) {
+            debug!("waiting for the pending modification of {:?}", path);
+            pending = self.modified.wait(pending).unwrap();
+        }
+    }
+
+    /// Runs `job` (named `what` in errors) by a worker, or by a thread of its own without workers, not by a FUSE thread
+    pub(super) fn run_later<F: FnOnce() + Send + 'static>(&self, what: &str, job: F) {
+        match &self.workers {
+            Some(workers) => workers.submit(job),
+            None => {
+                let spawned = thread::Builder::new()
+                    .name("projfs-job".to_string())
+                    .spawn(job);
+                if let Err(e) = spawned {
+                    error!("{} thread can't be spawned: {}", what, e);
+                }
+            }
+        }
+    }
+}
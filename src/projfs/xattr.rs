@@ -0,0 +1,58 @@
+use std::os::unix::ffi::OsStrExt;
+
+use fuse_mt::*;
+
+use crate::libc_bridge::libc;
+
+use super::{AccessType, ProjectionFS, Resolved};
+
+/// The prefix of the (read-only) virtual extended attributes of the projected and sidecar files
+pub(super) const VIRTUAL_XATTR_PREFIX: &str = "user.projfs.";
+
+/// The names of the virtual extended attributes, without `VIRTUAL_XATTR_PREFIX`
+pub(super) const VIRTUAL_XATTRS: [&str; 4] = ["source", "rule", "status", "error"];
+
+impl ProjectionFS {
+    /// The value of the virtual extended attribute `name` (without `VIRTUAL_XATTR_PREFIX`) of the projected or sidecar file `resolved`, if it has one
+    pub(super) fn virtual_xattr(&self, resolved: &Resolved, name: &str) -> Option<Vec<u8>> {
+        let value = match name {
+            "source" => resolved.source.as_os_str().as_bytes().to_vec(),
+            "rule" => match &resolved.access_type {
+                AccessType::Projected => b"projection".to_vec(),
+                AccessType::Sidecar(index) => {
+                    format!("sidecar {}", self.pm.spec.sidecars()[*index].suffix).into_bytes()
+                }
+                AccessType::PassThrough => return None,
+            },
+            "status" => {
+                let status = self.pm.status(
+                    &self.source_path(&resolved.source),
+                    &resolved.real,
+                    &resolved.access_type,
+                );
+                status.name().as_bytes().to_vec()
+            }
+            "error" => self
+                .pm
+                .failures
+                .lock()
+                .unwrap()
+                .get(&resolved.real)?
+                .clone()
+                .into_bytes(),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+/// Replies to `getxattr()` or `listxattr()` with `data`, or with its size if `size` is `0`
+pub(super) fn xattr_reply(data: Vec<u8>, size: u32) -> ResultXattr {
+    if size == 0 {
+        Ok(Xattr::Size(data.len() as u32))
+    } else if data.len() > size as usize {
+        Err(libc::ERANGE)
+    } else {
+        Ok(Xattr::Data(data))
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr, OsString};
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use crate::fsop;
+use crate::libc_bridge::libc;
+
+const WATCH_MASK: u32 = libc::IN_CREATE
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO
+    | libc::IN_ONLYDIR;
+
+/// How long the `IN_MOVED_TO` event of a rename is waited for, after its `IN_MOVED_FROM` event, before taking it as a move out of the source directory
+const MOVED_TO_TIMEOUT_MS: libc::c_int = 100;
+
+/// A change in the source directory.
+/// Every path is the relative partial path (with the leading `/`) in the source directory.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// An entry appeared (created, or moved from outside of the source directory)
+    Created { path: PathBuf, is_dir: bool },
+    /// The content of a file has been written
+    Modified(PathBuf),
+    /// An entry disappeared (deleted, or moved out of the source directory)
+    Removed { path: PathBuf, is_dir: bool },
+    /// An entry has been moved within the source directory
+    Renamed {
+        from: PathBuf,
+        to: PathBuf,
+        is_dir: bool,
+    },
+}
+
+/// Starts watching `source_dir` (recursively) in a dedicated thread, which reports the changes through the returned channel.
+/// The thread stops when the channel is closed.
+pub fn watch(source_dir: OsString) -> io::Result<Receiver<Change>> {
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut watcher = Watcher {
+        fd: fd,
+        source_dir: source_dir,
+        watches: HashMap::new(),
+        moved_from: None,
+    };
+    watcher.add(Path::new("/"))?;
+    watcher.add_subdirectories(Path::new("/"));
+    let (sender, receiver) = channel();
+    thread::Builder::new()
+        .name("projfs-watcher".to_string())
+        .spawn(move || watcher.run(sender))?;
+    Ok(receiver)
+}
+
+struct Watcher {
+    fd: libc::c_int,
+    source_dir: OsString,
+    /// The watched directories (relative partial paths) by watch descriptor
+    watches: HashMap<libc::c_int, PathBuf>,
+    /// The last `IN_MOVED_FROM` event (cookie, path and whether it's a directory), whose `IN_MOVED_TO` event may come in the next read
+    moved_from: Option<(u32, PathBuf, bool)>,
+}
+
+impl Watcher {
+    fn add(&mut self, dir_partial: &Path) -> io::Result<()> {
+        let real = fsop::real_path(&self.source_dir, dir_partial);
+        let real_c = CString::new(real.as_bytes())
+            .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+        let wd = unsafe { libc::inotify_add_watch(self.fd, real_c.as_ptr(), WATCH_MASK) };
+        if wd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        self.watches.insert(wd, dir_partial.to_owned());
+        Ok(())
+    }
+
+    /// Watches the directories under `dir_partial` (recursively)
+    fn add_subdirectories(&mut self, dir_partial: &Path) {
+        let entries = match fs::read_dir(fsop::real_path(&self.source_dir, dir_partial)) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("can't list {:?} for watching: {}", dir_partial, e);
+                return;
+            }
+        };
+        for entry in entries.filter_map(Result::ok) {
+            if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                let partial = dir_partial.join(entry.file_name());
+                match self.add(&partial) {
+                    Ok(()) => self.add_subdirectories(&partial),
+                    Err(e) => warn!("can't watch {:?}: {}", partial, e),
+                }
+            }
+        }
+    }
+
+    /// Stops watching `dir_partial` and the directories under it
+    fn remove(&mut self, dir_partial: &Path) {
+        let fd = self.fd;
+        self.watches.retain(|wd, path| {
+            if path.starts_with(dir_partial) {
+                unsafe { libc::inotify_rm_watch(fd, *wd) };
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Updates the paths of the watches of `from` and the directories under it, which are now under `to`
+    fn rename(&mut self, from: &Path, to: &Path) {
+        for path in self.watches.values_mut() {
+            if let Ok(rest) = path.strip_prefix(from) {
+                *path = to.join(rest);
+            }
+        }
+    }
+
+    fn run(mut self, sender: Sender<Change>) {
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            if self.moved_from.is_some() && !self.wait_for_events() {
+                let (_, from, from_is_dir) = self.moved_from.take().unwrap();
+                if self.moved_out(from, from_is_dir, &sender).is_err() {
+                    debug!("watcher stopped");
+                    return;
+                }
+                continue;
+            }
+            let n =
+                unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if n == -1 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                error!("can't read inotify events: {}", e);
+                return;
+            }
+            if self.process(&buf[..n as usize], &sender).is_err() {
+                debug!("watcher stopped");
+                return;
+            }
+        }
+    }
+
+    /// Waits up to `MOVED_TO_TIMEOUT_MS` for events to read, and returns whether there are some
+    fn wait_for_events(&self) -> bool {
+        let mut fds = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        loop {
+            match unsafe { libc::poll(&mut fds, 1, MOVED_TO_TIMEOUT_MS) } {
+                -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+                n => return n != 0,
+            }
+        }
+    }
+
+    /// Sends the changes described by the inotify events in `buf`
+    fn process(&mut self, buf: &[u8], sender: &Sender<Change>) -> Result<(), ()> {
+        // A rename is reported as a `IN_MOVED_FROM` event immediately followed by the matching `IN_MOVED_TO` event,
+        // possibly in the next read (see `moved_from`)
+        let mut offset = 0;
+        while offset + mem::size_of::<libc::inotify_event>() <= buf.len() {
+            let event: libc::inotify_event =
+                unsafe { ptr::read_unaligned(buf[offset..].as_ptr() as *const _) };
+            let name_start = offset + mem::size_of::<libc::inotify_event>();
+            offset = name_start + event.len as usize;
+            let name: Vec<u8> = buf[name_start..offset.min(buf.len())]
+                .iter()
+                .take_while(|b| **b != 0)
+                .cloned()
+                .collect();
+
+            if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                warn!("inotify queue overflowed; some changes of the source directory are missed");
+                continue;
+            }
+            if event.mask & libc::IN_IGNORED != 0 {
+                self.watches.remove(&event.wd);
+                continue;
+            }
+            let path = match self.watches.get(&event.wd) {
+                Some(dir) if !name.is_empty() => dir.join(OsStr::from_bytes(&name)),
+                _ => continue,
+            };
+            let is_dir = event.mask & libc::IN_ISDIR != 0;
+
+            if let Some((cookie, from, from_is_dir)) = self.moved_from.take() {
+                if event.mask & libc::IN_MOVED_TO != 0 && event.cookie == cookie {
+                    if is_dir {
+                        self.rename(&from, &path);
+                    }
+                    sender
+                        .send(Change::Renamed {
+                            from: from,
+                            to: path,
+                            is_dir: is_dir,
+                        })
+                        .map_err(|_| ())?;
+                    continue;
+                }
+                self.moved_out(from, from_is_dir, sender)?;
+            }
+
+            let change = if event.mask & libc::IN_CREATE != 0 || event.mask & libc::IN_MOVED_TO != 0
+            {
+                if is_dir {
+                    match self.add(&path) {
+                        Ok(()) => self.add_subdirectories(&path),
+                        Err(e) => warn!("can't watch {:?}: {}", path, e),
+                    }
+                }
+                Change::Created {
+                    path: path,
+                    is_dir: is_dir,
+                }
+            } else if event.mask & libc::IN_CLOSE_WRITE != 0 {
+                Change::Modified(path)
+            } else if event.mask & libc::IN_DELETE != 0 {
+                Change::Removed {
+                    path: path,
+                    is_dir: is_dir,
+                }
+            } else if event.mask & libc::IN_MOVED_FROM != 0 {
+                self.moved_from = Some((event.cookie, path, is_dir));
+                continue;
+            } else {
+                continue;
+            };
+            sender.send(change).map_err(|_| ())?;
+        }
+        Ok(())
+    }
+
+    fn moved_out(
+        &mut self,
+        path: PathBuf,
+        is_dir: bool,
+        sender: &Sender<Change>,
+    ) -> Result<(), ()> {
+        if is_dir {
+            self.remove(&path);
+        }
+        sender
+            .send(Change::Removed {
+                path: path,
+                is_dir: is_dir,
+            })
+            .map_err(|_| ())
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The bytes of an inotify event on `name` in the directory watched as `1`
+    fn event(mask: u32, cookie: u32, name: &str) -> Vec<u8> {
+        let len = (name.len() / 4 + 1) * 4;
+        let header = libc::inotify_event {
+            wd: 1,
+            mask: mask,
+            cookie: cookie,
+            len: len as u32,
+        };
+        let mut bytes = unsafe {
+            std::slice::from_raw_parts(
+                &header as *const _ as *const u8,
+                mem::size_of::<libc::inotify_event>(),
+            )
+        }
+        .to_vec();
+        bytes.extend(name.as_bytes());
+        bytes.resize(bytes.len() + len - name.len(), 0);
+        bytes
+    }
+
+    fn watcher() -> Watcher {
+        let mut watches = HashMap::new();
+        watches.insert(1, PathBuf::from("/album"));
+        Watcher {
+            fd: -1,
+            source_dir: OsString::from("/nonexistent"),
+            watches: watches,
+            moved_from: None,
+        }
+    }
+
+    #[test]
+    fn renames_split_across_reads_are_paired() {
+        let mut watcher = watcher();
+        let (sender, receiver) = channel();
+        watcher
+            .process(&event(libc::IN_MOVED_FROM, 7, "a.flac"), &sender)
+            .unwrap();
+        assert_eq!(receiver.try_recv().ok(), None);
+        watcher
+            .process(&event(libc::IN_MOVED_TO, 7, "b.flac"), &sender)
+            .unwrap();
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![Change::Renamed {
+                from: PathBuf::from("/album/a.flac"),
+                to: PathBuf::from("/album/b.flac"),
+                is_dir: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn unmatched_moves_are_removals() {
+        let mut watcher = watcher();
+        let (sender, receiver) = channel();
+        let mut events = event(libc::IN_MOVED_FROM, 7, "a.flac");
+        events.extend(event(libc::IN_CREATE, 0, "c.flac"));
+        watcher.process(&events, &sender).unwrap();
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![
+                Change::Removed {
+                    path: PathBuf::from("/album/a.flac"),
+                    is_dir: false,
+                },
+                Change::Created {
+                    path: PathBuf::from("/album/c.flac"),
+                    is_dir: false,
+                },
+            ]
+        );
+    }
+}
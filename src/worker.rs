@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// The jobs waiting for a worker
+struct Queue {
+    jobs: VecDeque<Job>,
+    /// Set when the pool is dropped, which stops the workers
+    closed: bool,
+}
+
+/// A fixed number of threads running jobs in the background, in the order they are submitted
+pub struct WorkerPool {
+    queue: Arc<(Mutex<Queue>, Condvar)>,
+}
+
+impl WorkerPool {
+    pub fn new(size: usize) -> WorkerPool {
+        let queue = Arc::new((
+            Mutex::new(Queue {
+                jobs: VecDeque::new(),
+                closed: false,
+            }),
+            Condvar::new(),
+        ));
+        for i in 0..size {
+            let queue = Arc::clone(&queue);
+            thread::Builder::new()
+                .name(format!("projfs-worker-{}", i))
+                .spawn(move || loop {
+                    let job = {
+                        let (jobs, available) = &*queue;
+                        let mut jobs = jobs.lock().unwrap();
+                        loop {
+                            if let Some(job) = jobs.jobs.pop_front() {
+                                break job;
+                            }
+                            if jobs.closed {
+                                return;
+                            }
+                            jobs = available.wait(jobs).unwrap();
+                        }
+                    };
+                    if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                        error!("background job panicked");
+                    }
+                })
+                .expect("worker thread can't be spawned");
+        }
+        WorkerPool { queue: queue }
+    }
+
+    pub fn submit<F: FnOnce() + Send + 'static>(&self, job: F) {
+        self.push(Box::new(job), false);
+    }
+
+    /// Same as `submit()`, but the job runs before the ones already waiting (e.g. for a file someone is waiting for)
+    pub fn submit_first<F: FnOnce() + Send + 'static>(&self, job: F) {
+        self.push(Box::new(job), true);
+    }
+
+    fn push(&self, job: Job, first: bool) {
+        let (jobs, available) = &*self.queue;
+        let mut jobs = jobs.lock().unwrap();
+        if jobs.closed {
+            error!("background job can't be submitted: the workers are stopped");
+            return;
+        }
+        if first {
+            jobs.jobs.push_front(job);
+        } else {
+            jobs.jobs.push_back(job);
+        }
+        available.notify_one();
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        let (jobs, available) = &*self.queue;
+        jobs.lock().unwrap().closed = true;
+        available.notify_all();
+    }
+}